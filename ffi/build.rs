@@ -0,0 +1,15 @@
+use std::env;
+use std::path::Path;
+
+// Keeps include/chip8.h in step with the exported functions
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let crate_dir = Path::new(&crate_dir);
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("Unable to read cbindgen.toml");
+    cbindgen::generate_with_config(crate_dir, config)
+        .expect("Unable to generate the C header")
+        .write_to_file(crate_dir.join("include/chip8.h"));
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=src");
+}
@@ -0,0 +1,155 @@
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A VM along with the keys held down and the last error
+typedef struct Chip8 Chip8;
+
+// Creates a VM running `rom` in `mode`: 0 for CHIP-8, 1 for SUPER-CHIP and
+// 2 for XO-CHIP, with that mode's default quirks. Returns NULL if the mode
+// is unknown or the ROM doesn't fit in memory.
+//
+// # Safety
+//
+// `rom` must point to `rom_length` readable bytes.
+Chip8 *chip8_new(const uint8_t *rom, size_t rom_length, uint8_t mode);
+
+// # Safety
+//
+// `chip8` must come from `chip8_new`, or be NULL, and is invalid afterwards.
+void chip8_free(Chip8 *chip8);
+
+// Reason for the last failure, owned by the VM, or NULL
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+const char *chip8_last_error(const Chip8 *chip8);
+
+// Runs a single instruction
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+int32_t chip8_step(Chip8 *chip8);
+
+// Runs `instructions` instructions then ticks the timers, to be called 60
+// times per second
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+int32_t chip8_run_frame(Chip8 *chip8, uint32_t instructions);
+
+// Presses or releases one of the keys 0x0 to 0xF, other values are ignored
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+void chip8_set_key(Chip8 *chip8, uint8_t key, bool pressed);
+
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+bool chip8_is_halted(const Chip8 *chip8);
+
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+bool chip8_is_sound_playing(const Chip8 *chip8);
+
+// Width of the screen in pixels, 64 or 128 in high resolution
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+size_t chip8_width(const Chip8 *chip8);
+
+// Height of the screen in pixels, 32 or 64 in high resolution
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+size_t chip8_height(const Chip8 *chip8);
+
+// Copies the screen as 0RGB pixels, row by row, into `buffer` and returns
+// the number of pixels. Nothing is copied if `buffer` is NULL or shorter
+// than `width * height`.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new` and `buffer` must point to `length`
+// writable pixels.
+size_t chip8_framebuffer(const Chip8 *chip8, uint32_t *buffer, size_t length);
+
+// Copies the screen as pixel values, 0 for off and one bit per XO-CHIP
+// plane, for frontends with their own palettes. Sizes work as in
+// `chip8_framebuffer`.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new` and `buffer` must point to `length`
+// writable bytes.
+size_t chip8_pixels(const Chip8 *chip8, uint8_t *buffer, size_t length);
+
+// Value of V0 to VF, or 0 for other indices
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+uint8_t chip8_register(const Chip8 *chip8, uint8_t index);
+
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+uint16_t chip8_index(const Chip8 *chip8);
+
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+uint16_t chip8_program_counter(const Chip8 *chip8);
+
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+uint8_t chip8_delay_timer(const Chip8 *chip8);
+
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+uint8_t chip8_sound_timer(const Chip8 *chip8);
+
+// Copies `length` bytes of memory starting at `address` into `buffer`,
+// failing if `buffer` is NULL
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new` and `buffer` must be NULL or point to
+// `length` writable bytes.
+int32_t chip8_read_memory(Chip8 *chip8, uint16_t address, uint8_t *buffer, size_t length);
+
+// Writes a save state into `buffer` and returns its length. Nothing is
+// written if `buffer` is NULL or shorter than that, so calling with NULL
+// first gives the size to allocate.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new` and `buffer` must point to `length`
+// writable bytes.
+size_t chip8_save_state(const Chip8 *chip8, uint8_t *buffer, size_t length);
+
+// Restores a state written by `chip8_save_state`, leaving the VM untouched
+// if it is invalid or `state` is NULL
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new` and `state` must be NULL or point to
+// `length` readable bytes.
+int32_t chip8_load_state(Chip8 *chip8, const uint8_t *state, size_t length);
+
+#endif /* CHIP8_H */
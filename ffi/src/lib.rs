@@ -0,0 +1,390 @@
+//! C bindings for the `chip-8` library, declared in `include/chip8.h`.
+//!
+//! Functions returning `int32_t` return 0 on success and -1 on failure, with
+//! the reason available from `chip8_last_error` until the next failure.
+
+use chip_8::engine::register::Register;
+use chip_8::{Key, Mode, VM};
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::ptr;
+
+/// A VM along with the keys held down and the last error
+pub struct Chip8 {
+    vm: VM,
+    keys: Vec<Key>,
+    error: Option<CString>,
+}
+
+impl Chip8 {
+    fn result<E: ToString>(&mut self, result: Result<(), E>) -> i32 {
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                self.error = CString::new(e.to_string()).ok();
+                -1
+            }
+        }
+    }
+}
+
+/// Creates a VM running `rom` in `mode`: 0 for CHIP-8, 1 for SUPER-CHIP and
+/// 2 for XO-CHIP, with that mode's default quirks. Returns NULL if the mode
+/// is unknown or the ROM doesn't fit in memory.
+///
+/// # Safety
+///
+/// `rom` must point to `rom_length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_new(rom: *const u8, rom_length: usize, mode: u8) -> *mut Chip8 {
+    if rom.is_null() {
+        return ptr::null_mut();
+    }
+    let mode = match Mode::from_id(mode) {
+        Some(mode) => mode,
+        None => return ptr::null_mut(),
+    };
+    let rom = std::slice::from_raw_parts(rom, rom_length);
+    match VM::new(rom, mode, mode.default_quirks()) {
+        Ok(vm) => Box::into_raw(Box::new(Chip8 {
+            vm,
+            keys: vec![],
+            error: None,
+        })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`, or be NULL, and is invalid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+/// Reason for the last failure, owned by the VM, or NULL
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_last_error(chip8: *const Chip8) -> *const c_char {
+    match &(*chip8).error {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Runs a single instruction
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(chip8: *mut Chip8) -> i32 {
+    let chip8 = &mut *chip8;
+    let result = chip8.vm.step_instruction(&chip8.keys);
+    chip8.result(result)
+}
+
+/// Runs `instructions` instructions then ticks the timers, to be called 60
+/// times per second
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(chip8: *mut Chip8, instructions: u32) -> i32 {
+    let chip8 = &mut *chip8;
+    let result = chip8.vm.run_frame(&chip8.keys, instructions as usize);
+    chip8.result(result)
+}
+
+/// Presses or releases one of the keys 0x0 to 0xF, other values are ignored
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, pressed: bool) {
+    let chip8 = &mut *chip8;
+    if let Some(key) = Key::from_value(key) {
+        chip8.keys.retain(|held| *held != key);
+        if pressed {
+            chip8.keys.push(key);
+        }
+    }
+}
+
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_is_halted(chip8: *const Chip8) -> bool {
+    (*chip8).vm.is_halted()
+}
+
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_is_sound_playing(chip8: *const Chip8) -> bool {
+    (*chip8).vm.is_sound_playing()
+}
+
+/// Width of the screen in pixels, 64 or 128 in high resolution
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_width(chip8: *const Chip8) -> usize {
+    (*chip8).vm.resolution().0
+}
+
+/// Height of the screen in pixels, 32 or 64 in high resolution
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_height(chip8: *const Chip8) -> usize {
+    (*chip8).vm.resolution().1
+}
+
+/// Copies the screen as 0RGB pixels, row by row, into `buffer` and returns
+/// the number of pixels. Nothing is copied if `buffer` is NULL or shorter
+/// than `width * height`.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new` and `buffer` must point to `length`
+/// writable pixels.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(
+    chip8: *const Chip8,
+    buffer: *mut u32,
+    length: usize,
+) -> usize {
+    let frame = (*chip8).vm.get_current_frame();
+    if !buffer.is_null() && length >= frame.len() {
+        ptr::copy_nonoverlapping(frame.as_ptr(), buffer, frame.len());
+    }
+    frame.len()
+}
+
+/// Copies the screen as pixel values, 0 for off and one bit per XO-CHIP
+/// plane, for frontends with their own palettes. Sizes work as in
+/// `chip8_framebuffer`.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new` and `buffer` must point to `length`
+/// writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_pixels(
+    chip8: *const Chip8,
+    buffer: *mut u8,
+    length: usize,
+) -> usize {
+    let pixels = (*chip8).vm.pixels();
+    if !buffer.is_null() && length >= pixels.len() {
+        ptr::copy_nonoverlapping(pixels.as_ptr(), buffer, pixels.len());
+    }
+    pixels.len()
+}
+
+/// Value of V0 to VF, or 0 for other indices
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_register(chip8: *const Chip8, index: u8) -> u8 {
+    match Register::new(index as u16) {
+        Ok(register) => (*chip8).vm.cpu().registers().read(&register),
+        Err(_) => 0,
+    }
+}
+
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_index(chip8: *const Chip8) -> u16 {
+    (*chip8).vm.cpu().registers().i
+}
+
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_program_counter(chip8: *const Chip8) -> u16 {
+    (*chip8).vm.cpu().program_counter()
+}
+
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_delay_timer(chip8: *const Chip8) -> u8 {
+    (*chip8).vm.cpu().registers().dt
+}
+
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_timer(chip8: *const Chip8) -> u8 {
+    (*chip8).vm.cpu().registers().st
+}
+
+/// Copies `length` bytes of memory starting at `address` into `buffer`,
+/// failing if `buffer` is NULL
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new` and `buffer` must be NULL or point to
+/// `length` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_read_memory(
+    chip8: *mut Chip8,
+    address: u16,
+    buffer: *mut u8,
+    length: usize,
+) -> i32 {
+    let chip8 = &mut *chip8;
+    if buffer.is_null() {
+        return chip8.result(Err("buffer is NULL"));
+    }
+    let buffer = std::slice::from_raw_parts_mut(buffer, length);
+    let mut result = Ok(());
+    for (offset, byte) in buffer.iter_mut().enumerate() {
+        let read = u16::try_from(address as usize + offset)
+            .map_err(|_| String::from("read past the end of memory"))
+            .and_then(|address| chip8.vm.memory().peek(address).map_err(|e| e.to_string()));
+        match read {
+            Ok(value) => *byte = value,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    chip8.result(result)
+}
+
+/// Writes a save state into `buffer` and returns its length. Nothing is
+/// written if `buffer` is NULL or shorter than that, so calling with NULL
+/// first gives the size to allocate.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new` and `buffer` must point to `length`
+/// writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(
+    chip8: *const Chip8,
+    buffer: *mut u8,
+    length: usize,
+) -> usize {
+    let state = (*chip8).vm.save_state();
+    if !buffer.is_null() && length >= state.len() {
+        ptr::copy_nonoverlapping(state.as_ptr(), buffer, state.len());
+    }
+    state.len()
+}
+
+/// Restores a state written by `chip8_save_state`, leaving the VM untouched
+/// if it is invalid or `state` is NULL
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new` and `state` must be NULL or point to
+/// `length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(
+    chip8: *mut Chip8,
+    state: *const u8,
+    length: usize,
+) -> i32 {
+    let chip8 = &mut *chip8;
+    if state.is_null() {
+        return chip8.result(Err("state is NULL"));
+    }
+    let state = std::slice::from_raw_parts(state, length);
+    let result = chip8.vm.load_state(state);
+    chip8.result(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    // ADD V0, 1; JP 0x200
+    const ROM: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    unsafe fn save(chip8: *const Chip8) -> Vec<u8> {
+        let mut state = vec![0; chip8_save_state(chip8, ptr::null_mut(), 0)];
+        chip8_save_state(chip8, state.as_mut_ptr(), state.len());
+        state
+    }
+
+    unsafe fn last_error(chip8: *const Chip8) -> String {
+        CStr::from_ptr(chip8_last_error(chip8))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        unsafe {
+            let chip8 = chip8_new(ROM.as_ptr(), ROM.len(), 0);
+            assert_eq!(chip8_run_frame(chip8, 10), 0);
+            let state = save(chip8);
+            let (v0, pc) = (chip8_register(chip8, 0), chip8_program_counter(chip8));
+
+            assert_eq!(chip8_run_frame(chip8, 10), 0);
+            assert_ne!(chip8_register(chip8, 0), v0);
+            assert_eq!(chip8_load_state(chip8, state.as_ptr(), state.len()), 0);
+            assert_eq!(chip8_register(chip8, 0), v0);
+            assert_eq!(chip8_program_counter(chip8), pc);
+            assert_eq!(save(chip8), state);
+            chip8_free(chip8);
+        }
+    }
+
+    #[test]
+    fn invalid_states_leave_the_vm_untouched() {
+        unsafe {
+            let chip8 = chip8_new(ROM.as_ptr(), ROM.len(), 0);
+            assert_eq!(chip8_run_frame(chip8, 10), 0);
+            let state = save(chip8);
+
+            // The version follows the 4 byte magic
+            let mut newer = state.clone();
+            newer[4] += 1;
+            assert_eq!(chip8_load_state(chip8, newer.as_ptr(), newer.len()), -1);
+            assert_eq!(
+                last_error(chip8),
+                format!("unsupported save state version {}", newer[4])
+            );
+
+            let truncated = &state[..state.len() - 1];
+            assert_eq!(
+                chip8_load_state(chip8, truncated.as_ptr(), truncated.len()),
+                -1
+            );
+            assert_eq!(chip8_load_state(chip8, ptr::null(), state.len()), -1);
+            assert_eq!(last_error(chip8), "state is NULL");
+
+            assert_eq!(save(chip8), state);
+            chip8_free(chip8);
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! A minimal libretro frontend for trying the core without RetroArch: loads
+//! the shared library, runs a ROM headless with some buttons held, prints the
+//! last frame and checks that a save state replays identically.
+//!
+//! cargo build -p chip-8-libretro
+//! cargo run -p chip-8-libretro --example frontend -- \
+//!     target/debug/libchip8_libretro.so roms/BRIX --frames 300 --hold right
+
+use chip8_libretro::retro::*;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::process;
+use std::sync::{Mutex, MutexGuard};
+use std::{env, mem, slice};
+
+struct Frontend {
+    options: Vec<(CString, CString)>,
+    held: Vec<u32>,
+    frame: Vec<u32>,
+    width: usize,
+    height: usize,
+    frames: usize,
+    samples: usize,
+    audible_samples: usize,
+}
+
+static FRONTEND: Mutex<Frontend> = Mutex::new(Frontend {
+    options: vec![],
+    held: vec![],
+    frame: vec![],
+    width: 0,
+    height: 0,
+    frames: 0,
+    samples: 0,
+    audible_samples: 0,
+});
+
+fn frontend() -> MutexGuard<'static, Frontend> {
+    FRONTEND.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+unsafe extern "C" fn environment(command: u32, data: *mut c_void) -> bool {
+    match command {
+        ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const u32) == PIXEL_FORMAT_XRGB8888,
+        ENVIRONMENT_SET_VARIABLES => {
+            let mut variable = data as *const Variable;
+            while !(*variable).key.is_null() {
+                println!(
+                    "option {}: {}",
+                    CStr::from_ptr((*variable).key).to_string_lossy(),
+                    CStr::from_ptr((*variable).value).to_string_lossy()
+                );
+                variable = variable.add(1);
+            }
+            true
+        }
+        ENVIRONMENT_GET_VARIABLE => {
+            let variable = &mut *(data as *mut Variable);
+            let key = CStr::from_ptr(variable.key);
+            match frontend().options.iter().find(|(name, _)| **name == *key) {
+                Some((_, value)) => {
+                    variable.value = value.as_ptr();
+                    true
+                }
+                None => false,
+            }
+        }
+        ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = false;
+            true
+        }
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: u32, height: u32, pitch: usize) {
+    let mut frontend = frontend();
+    frontend.frames += 1;
+    if data.is_null() {
+        return;
+    }
+    let (width, height) = (width as usize, height as usize);
+    let rows = slice::from_raw_parts(data as *const u8, pitch * height);
+    frontend.frame = rows
+        .chunks(pitch)
+        .flat_map(|row| {
+            row[..width * 4]
+                .chunks(4)
+                .map(|pixel| u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+        })
+        .collect();
+    frontend.width = width;
+    frontend.height = height;
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = slice::from_raw_parts(data, frames * 2);
+    let mut frontend = frontend();
+    frontend.samples += frames;
+    frontend.audible_samples += samples.chunks(2).filter(|pair| pair[0] != 0).count();
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: u32, device: u32, _index: u32, id: u32) -> i16 {
+    (port == 0 && device == DEVICE_JOYPAD && frontend().held.contains(&id)) as i16
+}
+
+struct Core {
+    api_version: unsafe extern "C" fn() -> u32,
+    set_environment: unsafe extern "C" fn(EnvironmentFn),
+    set_video_refresh: unsafe extern "C" fn(VideoRefreshFn),
+    set_audio_sample: unsafe extern "C" fn(AudioSampleFn),
+    set_audio_sample_batch: unsafe extern "C" fn(AudioSampleBatchFn),
+    set_input_poll: unsafe extern "C" fn(InputPollFn),
+    set_input_state: unsafe extern "C" fn(InputStateFn),
+    init: unsafe extern "C" fn(),
+    deinit: unsafe extern "C" fn(),
+    get_system_info: unsafe extern "C" fn(*mut SystemInfo),
+    get_system_av_info: unsafe extern "C" fn(*mut SystemAvInfo),
+    load_game: unsafe extern "C" fn(*const GameInfo) -> bool,
+    unload_game: unsafe extern "C" fn(),
+    run: unsafe extern "C" fn(),
+    serialize_size: unsafe extern "C" fn() -> usize,
+    serialize: unsafe extern "C" fn(*mut c_void, usize) -> bool,
+    unserialize: unsafe extern "C" fn(*const c_void, usize) -> bool,
+}
+
+impl Core {
+    unsafe fn open(path: &str) -> Result<Core, String> {
+        let path = CString::new(path).map_err(|e| e.to_string())?;
+        let library = libc::dlopen(path.as_ptr(), libc::RTLD_NOW);
+        if library.is_null() {
+            return Err(CStr::from_ptr(libc::dlerror())
+                .to_string_lossy()
+                .into_owned());
+        }
+        Ok(Core {
+            api_version: symbol(library, "retro_api_version")?,
+            set_environment: symbol(library, "retro_set_environment")?,
+            set_video_refresh: symbol(library, "retro_set_video_refresh")?,
+            set_audio_sample: symbol(library, "retro_set_audio_sample")?,
+            set_audio_sample_batch: symbol(library, "retro_set_audio_sample_batch")?,
+            set_input_poll: symbol(library, "retro_set_input_poll")?,
+            set_input_state: symbol(library, "retro_set_input_state")?,
+            init: symbol(library, "retro_init")?,
+            deinit: symbol(library, "retro_deinit")?,
+            get_system_info: symbol(library, "retro_get_system_info")?,
+            get_system_av_info: symbol(library, "retro_get_system_av_info")?,
+            load_game: symbol(library, "retro_load_game")?,
+            unload_game: symbol(library, "retro_unload_game")?,
+            run: symbol(library, "retro_run")?,
+            serialize_size: symbol(library, "retro_serialize_size")?,
+            serialize: symbol(library, "retro_serialize")?,
+            unserialize: symbol(library, "retro_unserialize")?,
+        })
+    }
+
+    unsafe fn save_state(&self) -> Result<Vec<u8>, String> {
+        let mut state = vec![0; (self.serialize_size)()];
+        if (self.serialize)(state.as_mut_ptr() as *mut c_void, state.len()) {
+            Ok(state)
+        } else {
+            Err(String::from("retro_serialize failed"))
+        }
+    }
+
+    // Runs `frames` frames and returns the last one
+    unsafe fn run_frames(&self, frames: usize) -> Vec<u32> {
+        for _ in 0..frames {
+            (self.run)();
+        }
+        frontend().frame.clone()
+    }
+}
+
+unsafe fn symbol<T: Copy>(library: *mut c_void, name: &str) -> Result<T, String> {
+    let name = CString::new(name).unwrap();
+    let address = libc::dlsym(library, name.as_ptr());
+    if address.is_null() {
+        Err(format!("missing symbol {}", name.to_string_lossy()))
+    } else {
+        Ok(mem::transmute_copy(&address))
+    }
+}
+
+fn button(name: &str) -> Option<u32> {
+    match name {
+        "b" => Some(DEVICE_ID_JOYPAD_B),
+        "y" => Some(DEVICE_ID_JOYPAD_Y),
+        "select" => Some(DEVICE_ID_JOYPAD_SELECT),
+        "start" => Some(DEVICE_ID_JOYPAD_START),
+        "up" => Some(DEVICE_ID_JOYPAD_UP),
+        "down" => Some(DEVICE_ID_JOYPAD_DOWN),
+        "left" => Some(DEVICE_ID_JOYPAD_LEFT),
+        "right" => Some(DEVICE_ID_JOYPAD_RIGHT),
+        "a" => Some(DEVICE_ID_JOYPAD_A),
+        "x" => Some(DEVICE_ID_JOYPAD_X),
+        "l" => Some(DEVICE_ID_JOYPAD_L),
+        "r" => Some(DEVICE_ID_JOYPAD_R),
+        "l2" => Some(DEVICE_ID_JOYPAD_L2),
+        "r2" => Some(DEVICE_ID_JOYPAD_R2),
+        "l3" => Some(DEVICE_ID_JOYPAD_L3),
+        "r3" => Some(DEVICE_ID_JOYPAD_R3),
+        _ => None,
+    }
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn usage() -> ! {
+    exit_with_error(
+        "usage: frontend CORE ROM [--frames N] [--hold BUTTON]... [--option KEY=VALUE]...",
+    )
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let core_path = args.next().unwrap_or_else(|| usage());
+    let rom_path = args.next().unwrap_or_else(|| usage());
+    let mut frames = 600;
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--frames" => frames = value.parse().unwrap_or_else(|_| usage()),
+            "--hold" => frontend()
+                .held
+                .push(button(&value).unwrap_or_else(|| usage())),
+            "--option" => {
+                let mut parts = value.splitn(2, '=');
+                let key = parts.next().unwrap();
+                let value = parts.next().unwrap_or_else(|| usage());
+                frontend()
+                    .options
+                    .push((CString::new(key).unwrap(), CString::new(value).unwrap()));
+            }
+            _ => usage(),
+        }
+    }
+    let rom = std::fs::read(&rom_path).unwrap_or_else(|e| exit_with_error(&e.to_string()));
+
+    unsafe {
+        let core = Core::open(&core_path).unwrap_or_else(|e| exit_with_error(&e));
+        if (core.api_version)() != API_VERSION {
+            exit_with_error("unsupported libretro API version");
+        }
+        let mut info: SystemInfo = mem::zeroed();
+        (core.get_system_info)(&mut info);
+        println!(
+            "core: {} {} ({})",
+            CStr::from_ptr(info.library_name).to_string_lossy(),
+            CStr::from_ptr(info.library_version).to_string_lossy(),
+            CStr::from_ptr(info.valid_extensions).to_string_lossy()
+        );
+
+        (core.set_environment)(environment);
+        (core.set_video_refresh)(video_refresh);
+        (core.set_audio_sample)(audio_sample);
+        (core.set_audio_sample_batch)(audio_sample_batch);
+        (core.set_input_poll)(input_poll);
+        (core.set_input_state)(input_state);
+        (core.init)();
+
+        let path = CString::new(rom_path).unwrap();
+        let game = GameInfo {
+            path: path.as_ptr() as *const c_char,
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        if !(core.load_game)(&game) {
+            exit_with_error("retro_load_game failed");
+        }
+        let mut av_info: SystemAvInfo = mem::zeroed();
+        (core.get_system_av_info)(&mut av_info);
+        println!(
+            "video: {}x{} up to {}x{} at {}fps, audio: {}Hz",
+            av_info.geometry.base_width,
+            av_info.geometry.base_height,
+            av_info.geometry.max_width,
+            av_info.geometry.max_height,
+            av_info.timing.fps,
+            av_info.timing.sample_rate
+        );
+
+        let frame = core.run_frames(frames);
+        {
+            let frontend = frontend();
+            // Whatever the palette, most of the screen is background
+            let background = frame
+                .iter()
+                .max_by_key(|color| frame.iter().filter(|pixel| pixel == color).count())
+                .map(|color| color & 0xffffff);
+            for row in frame.chunks(frontend.width.max(1)) {
+                let line: String = row
+                    .iter()
+                    .map(|pixel| {
+                        if Some(pixel & 0xffffff) != background {
+                            '#'
+                        } else {
+                            ' '
+                        }
+                    })
+                    .collect();
+                println!("{}", line.trim_end());
+            }
+            println!(
+                "frames: {}, resolution: {}x{}, audio: {} samples, {} audible",
+                frontend.frames,
+                frontend.width,
+                frontend.height,
+                frontend.samples,
+                frontend.audible_samples
+            );
+        }
+
+        let state = core.save_state().unwrap_or_else(|e| exit_with_error(&e));
+        let expected = core.run_frames(60);
+        if !(core.unserialize)(state.as_ptr() as *const c_void, state.len()) {
+            exit_with_error("retro_unserialize failed");
+        }
+        if core.run_frames(60) != expected {
+            exit_with_error("save state: replay differs");
+        }
+        println!("save state: {} bytes, replays identically", state.len());
+
+        (core.unload_game)();
+        (core.deinit)();
+    }
+}
@@ -0,0 +1,524 @@
+//! A libretro core, loaded by RetroArch and other frontends as
+//! `chip8_libretro.so`.
+//!
+//! The keypad is played with the keyboard, laid out like the desktop
+//! frontend, or with a RetroPad: the D-pad is 2/4/6/8, A is 5, B is 0, X is
+//! 1, Y is 3, L and R are 7 and 9, L2 and R2 are A and B, L3 and R3 are C and
+//! D, Select is E and Start is F.
+//!
+//! Save states are the VM's own, behind a little-endian `u32` length and
+//! padded to `VM::max_state_length` so their size doesn't change while a
+//! game runs.
+
+pub mod retro;
+
+use chip_8::engine::vm::{HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH};
+use chip_8::{Key, Mode, Palette, VM};
+use retro::*;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::{ptr, slice};
+
+const FRAMES_PER_SECOND: f64 = 60.0;
+const SAMPLE_RATE: f64 = 44100.0;
+const VOLUME: i16 = 0x1000;
+const PATTERN_BITS: f64 = 128.0;
+
+const MODE_OPTION: &[u8] = b"chip8_mode\0";
+const SPEED_OPTION: &[u8] = b"chip8_speed\0";
+const PALETTE_OPTION: &[u8] = b"chip8_palette\0";
+
+const JOYPAD: [(u32, Key); 16] = [
+    (DEVICE_ID_JOYPAD_UP, Key::Key2),
+    (DEVICE_ID_JOYPAD_LEFT, Key::Key4),
+    (DEVICE_ID_JOYPAD_RIGHT, Key::Key6),
+    (DEVICE_ID_JOYPAD_DOWN, Key::Key8),
+    (DEVICE_ID_JOYPAD_A, Key::Key5),
+    (DEVICE_ID_JOYPAD_B, Key::Key0),
+    (DEVICE_ID_JOYPAD_X, Key::Key1),
+    (DEVICE_ID_JOYPAD_Y, Key::Key3),
+    (DEVICE_ID_JOYPAD_L, Key::Key7),
+    (DEVICE_ID_JOYPAD_R, Key::Key9),
+    (DEVICE_ID_JOYPAD_L2, Key::KeyA),
+    (DEVICE_ID_JOYPAD_R2, Key::KeyB),
+    (DEVICE_ID_JOYPAD_L3, Key::KeyC),
+    (DEVICE_ID_JOYPAD_R3, Key::KeyD),
+    (DEVICE_ID_JOYPAD_SELECT, Key::KeyE),
+    (DEVICE_ID_JOYPAD_START, Key::KeyF),
+];
+
+// libretro key codes are lowercase ASCII
+const KEYBOARD: [(u8, Key); 16] = [
+    (b'1', Key::Key1),
+    (b'2', Key::Key2),
+    (b'3', Key::Key3),
+    (b'4', Key::KeyC),
+    (b'q', Key::Key4),
+    (b'w', Key::Key5),
+    (b'e', Key::Key6),
+    (b'r', Key::KeyD),
+    (b'a', Key::Key7),
+    (b's', Key::Key8),
+    (b'd', Key::Key9),
+    (b'f', Key::KeyE),
+    (b'z', Key::KeyA),
+    (b'x', Key::Key0),
+    (b'c', Key::KeyB),
+    (b'v', Key::KeyF),
+];
+
+struct Game {
+    rom: Vec<u8>,
+    mode: Mode,
+    vm: VM,
+    instructions_per_frame: usize,
+    palette: Palette,
+    failed: bool,
+    frame: Vec<u32>,
+    samples: Vec<i16>,
+    // Position in the audio pattern, in bits
+    phase: f64,
+}
+
+impl Game {
+    fn run_frame(&mut self, keys: &[Key]) {
+        if !self.failed {
+            if let Err(e) = self.vm.run_frame(keys, self.instructions_per_frame) {
+                eprintln!("chip-8: {}", e);
+                self.failed = true;
+            }
+        }
+        self.frame = self.vm.frame(&self.palette);
+        self.mix_audio();
+    }
+
+    fn mix_audio(&mut self) {
+        self.samples.clear();
+        let playing = self.vm.is_sound_playing();
+        let audio = self.vm.audio();
+        let step = audio.playback_rate() / SAMPLE_RATE;
+        for _ in 0..(SAMPLE_RATE / FRAMES_PER_SECOND) as usize {
+            let sample = if playing {
+                let bit = self.phase as usize;
+                self.phase = (self.phase + step) % PATTERN_BITS;
+                if audio.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    VOLUME
+                } else {
+                    -VOLUME
+                }
+            } else {
+                0
+            };
+            self.samples.push(sample);
+            self.samples.push(sample);
+        }
+    }
+}
+
+struct Core {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    game: Option<Game>,
+}
+
+static CORE: Mutex<Core> = Mutex::new(Core {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    game: None,
+});
+
+fn core() -> MutexGuard<'static, Core> {
+    CORE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Core {
+    unsafe fn option(&self, key: &'static [u8]) -> Option<String> {
+        let environment = self.environment?;
+        let mut variable = Variable {
+            key: key.as_ptr() as *const c_char,
+            value: ptr::null(),
+        };
+        if environment(
+            ENVIRONMENT_GET_VARIABLE,
+            &mut variable as *mut Variable as *mut c_void,
+        ) && !variable.value.is_null()
+        {
+            Some(
+                CStr::from_ptr(variable.value)
+                    .to_string_lossy()
+                    .into_owned(),
+            )
+        } else {
+            None
+        }
+    }
+
+    unsafe fn options_updated(&self) -> bool {
+        let mut updated = false;
+        match self.environment {
+            Some(environment) => {
+                environment(
+                    ENVIRONMENT_GET_VARIABLE_UPDATE,
+                    &mut updated as *mut bool as *mut c_void,
+                ) && updated
+            }
+            None => false,
+        }
+    }
+
+    unsafe fn instructions_per_frame(&self) -> usize {
+        self.option(SPEED_OPTION)
+            .and_then(|speed| speed.parse().ok())
+            .unwrap_or(10)
+    }
+
+    unsafe fn palette(&self) -> Palette {
+        self.option(PALETTE_OPTION)
+            .and_then(|name| Palette::from_name(&name))
+            .unwrap_or_default()
+    }
+
+    unsafe fn keys(&self) -> Vec<Key> {
+        let input_state = match self.input_state {
+            Some(input_state) => input_state,
+            None => return vec![],
+        };
+        let mut keys = vec![];
+        for (button, key) in JOYPAD.iter() {
+            if input_state(0, DEVICE_JOYPAD, 0, *button) != 0 {
+                keys.push(*key);
+            }
+        }
+        for (code, key) in KEYBOARD.iter() {
+            if input_state(0, DEVICE_KEYBOARD, 0, *code as u32) != 0 && !keys.contains(key) {
+                keys.push(*key);
+            }
+        }
+        keys
+    }
+}
+
+/// The mode chosen in the core options, or guessed from Octo's file
+/// extensions
+fn mode(option: Option<String>, path: Option<&Path>) -> Mode {
+    if let Some(mode) = option.as_deref().and_then(Mode::from_name) {
+        return mode;
+    }
+    match path
+        .and_then(|path| path.extension())
+        .and_then(|extension| extension.to_str())
+    {
+        Some("sc8") => Mode::SuperChip,
+        Some("xo8") => Mode::XoChip,
+        _ => Mode::Chip8,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> u32 {
+    API_VERSION
+}
+
+/// # Safety
+///
+/// `info` must point to a writable `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: b"CHIP-8\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|sc8|xo8\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info` must point to a writable `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: WIDTH as u32,
+            base_height: HEIGHT as u32,
+            max_width: HIRES_WIDTH as u32,
+            max_height: HIRES_HEIGHT as u32,
+            aspect_ratio: 2.0,
+        },
+        timing: SystemTiming {
+            fps: FRAMES_PER_SECOND,
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+/// Also declares the core options
+///
+/// # Safety
+///
+/// `environment` must be callable until the core is unloaded.
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(environment: EnvironmentFn) {
+    core().environment = Some(environment);
+    let mut variables = [
+        Variable {
+            key: MODE_OPTION.as_ptr() as *const c_char,
+            value: b"Mode (restart); auto|chip8|schip|xochip\0".as_ptr() as *const c_char,
+        },
+        Variable {
+            key: SPEED_OPTION.as_ptr() as *const c_char,
+            value: b"Instructions per frame; 10|15|20|30|50|100|200|500|1000\0".as_ptr()
+                as *const c_char,
+        },
+        Variable {
+            key: PALETTE_OPTION.as_ptr() as *const c_char,
+            value: b"Palette; default|amber|green|lcd|octo\0".as_ptr() as *const c_char,
+        },
+        Variable {
+            key: ptr::null(),
+            value: ptr::null(),
+        },
+    ];
+    environment(
+        ENVIRONMENT_SET_VARIABLES,
+        variables.as_mut_ptr() as *mut c_void,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: VideoRefreshFn) {
+    core().video_refresh = Some(video_refresh);
+}
+
+/// Unused, samples are sent a frame at a time
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: AudioSampleBatchFn) {
+    core().audio_sample_batch = Some(audio_sample_batch);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: InputPollFn) {
+    core().input_poll = Some(input_poll);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: InputStateFn) {
+    core().input_state = Some(input_state);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: u32, _device: u32) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(game) = core().game.as_mut() {
+        if let Ok(vm) = VM::new(&game.rom, game.mode, game.mode.default_quirks()) {
+            game.vm = vm;
+            game.failed = false;
+        }
+    }
+}
+
+/// Runs one frame: polls input, runs the VM, then sends the screen and a
+/// frame of audio
+///
+/// # Safety
+///
+/// The callbacks given to the `retro_set_*` functions must still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    let mut core = core();
+    if let Some(input_poll) = core.input_poll {
+        input_poll();
+    }
+    let keys = core.keys();
+    if core.options_updated() {
+        let instructions_per_frame = core.instructions_per_frame();
+        let palette = core.palette();
+        if let Some(game) = core.game.as_mut() {
+            game.instructions_per_frame = instructions_per_frame;
+            game.palette = palette;
+        }
+    }
+    let core = &mut *core;
+    let game = match core.game.as_mut() {
+        Some(game) => game,
+        None => return,
+    };
+    game.run_frame(&keys);
+    if let Some(video_refresh) = core.video_refresh {
+        let (width, height) = game.vm.resolution();
+        video_refresh(
+            game.frame.as_ptr() as *const c_void,
+            width as u32,
+            height as u32,
+            width * 4,
+        );
+    }
+    if let Some(audio_sample_batch) = core.audio_sample_batch {
+        audio_sample_batch(game.samples.as_ptr(), game.samples.len() / 2);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    match &core().game {
+        Some(game) => 4 + game.vm.max_state_length(),
+        None => 0,
+    }
+}
+
+/// # Safety
+///
+/// `data` must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = core();
+    let state = match &core.game {
+        Some(game) => game.vm.save_state(),
+        None => return false,
+    };
+    if data.is_null() || size < 4 + state.len() {
+        return false;
+    }
+    let buffer = slice::from_raw_parts_mut(data as *mut u8, size);
+    let (length, rest) = buffer.split_at_mut(4);
+    length.copy_from_slice(&(state.len() as u32).to_le_bytes());
+    let (saved, padding) = rest.split_at_mut(state.len());
+    saved.copy_from_slice(&state);
+    padding.iter_mut().for_each(|byte| *byte = 0);
+    true
+}
+
+/// # Safety
+///
+/// `data` must point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = core();
+    let game = match core.game.as_mut() {
+        Some(game) => game,
+        None => return false,
+    };
+    if data.is_null() || size < 4 {
+        return false;
+    }
+    let buffer = slice::from_raw_parts(data as *const u8, size);
+    let length = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+    match buffer[4..].get(..length) {
+        Some(state) if game.vm.load_state(state).is_ok() => {
+            game.failed = false;
+            true
+        }
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: u32, _enabled: bool, _code: *const c_char) {}
+
+/// Starts the ROM in `game`, in the mode from the core options
+///
+/// # Safety
+///
+/// `game` must point to a `retro_game_info` whose data is readable.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let mut core = core();
+    let environment = match core.environment {
+        Some(environment) => environment,
+        None => return false,
+    };
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut format as *mut u32 as *mut c_void,
+    ) {
+        eprintln!("chip-8: XRGB8888 isn't supported");
+        return false;
+    }
+    let game = &*game;
+    let path = if game.path.is_null() {
+        None
+    } else {
+        CStr::from_ptr(game.path).to_str().ok().map(Path::new)
+    };
+    let mode = mode(core.option(MODE_OPTION), path);
+    let rom = slice::from_raw_parts(game.data as *const u8, game.size).to_vec();
+    let vm = match VM::new(&rom, mode, mode.default_quirks()) {
+        Ok(vm) => vm,
+        Err(e) => {
+            eprintln!("chip-8: {}", e);
+            return false;
+        }
+    };
+    let instructions_per_frame = core.instructions_per_frame();
+    let palette = core.palette();
+    core.game = Some(Game {
+        rom,
+        mode,
+        vm,
+        instructions_per_frame,
+        palette,
+        failed: false,
+        frame: vec![],
+        samples: vec![],
+        phase: 0.0,
+    });
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: u32,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> u32 {
+    REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: u32) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: u32) -> usize {
+    0
+}
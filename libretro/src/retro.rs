@@ -0,0 +1,88 @@
+//! The parts of `libretro.h` the core uses.
+
+use std::os::raw::{c_char, c_void};
+
+pub const API_VERSION: u32 = 1;
+
+pub const DEVICE_JOYPAD: u32 = 1;
+pub const DEVICE_KEYBOARD: u32 = 3;
+
+pub const DEVICE_ID_JOYPAD_B: u32 = 0;
+pub const DEVICE_ID_JOYPAD_Y: u32 = 1;
+pub const DEVICE_ID_JOYPAD_SELECT: u32 = 2;
+pub const DEVICE_ID_JOYPAD_START: u32 = 3;
+pub const DEVICE_ID_JOYPAD_UP: u32 = 4;
+pub const DEVICE_ID_JOYPAD_DOWN: u32 = 5;
+pub const DEVICE_ID_JOYPAD_LEFT: u32 = 6;
+pub const DEVICE_ID_JOYPAD_RIGHT: u32 = 7;
+pub const DEVICE_ID_JOYPAD_A: u32 = 8;
+pub const DEVICE_ID_JOYPAD_X: u32 = 9;
+pub const DEVICE_ID_JOYPAD_L: u32 = 10;
+pub const DEVICE_ID_JOYPAD_R: u32 = 11;
+pub const DEVICE_ID_JOYPAD_L2: u32 = 12;
+pub const DEVICE_ID_JOYPAD_R2: u32 = 13;
+pub const DEVICE_ID_JOYPAD_L3: u32 = 14;
+pub const DEVICE_ID_JOYPAD_R3: u32 = 15;
+
+pub const ENVIRONMENT_SET_PIXEL_FORMAT: u32 = 10;
+pub const ENVIRONMENT_GET_VARIABLE: u32 = 15;
+pub const ENVIRONMENT_SET_VARIABLES: u32 = 16;
+pub const ENVIRONMENT_GET_VARIABLE_UPDATE: u32 = 17;
+
+pub const PIXEL_FORMAT_XRGB8888: u32 = 1;
+
+pub const REGION_NTSC: u32 = 0;
+
+pub type EnvironmentFn = unsafe extern "C" fn(command: u32, data: *mut c_void) -> bool;
+pub type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: u32, height: u32, pitch: usize);
+pub type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = unsafe extern "C" fn();
+pub type InputStateFn = unsafe extern "C" fn(port: u32, device: u32, index: u32, id: u32) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: u32,
+    pub base_height: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+/// A core option, `value` reads "Description; first|second|..." when set and
+/// is the chosen value when read back
+#[repr(C)]
+pub struct Variable {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
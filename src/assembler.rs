@@ -0,0 +1,501 @@
+use chip_8::engine::opcode::Opcode;
+use chip_8::engine::register::Register;
+use chip_8::engine::vm::PROGRAM_OFFSET;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Deep enough for any real program, shallow enough to catch include cycles
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_CONSTANT_DEPTH: usize = 16;
+
+// One line of source, after includes are expanded
+struct Line {
+    file: PathBuf,
+    number: usize,
+    text: String,
+}
+
+impl Line {
+    fn error(&self, message: String) -> String {
+        format!("{}:{}: {}", self.file.display(), self.number, message)
+    }
+}
+
+enum Symbol {
+    Label(u16),
+    Constant(String),
+}
+
+// Assembles the mnemonics printed by `disasm` into a ROM loaded at 0x200.
+// Besides instructions, a source holds `name:` labels, `name equ value`
+// constants, `db` and `dw` data and `include "file"` directives. Comments
+// start with `;`, and numbers are decimal, 0x hexadecimal or 0b binary.
+pub fn assemble(path: &Path) -> Result<Vec<u8>, String> {
+    let mut lines = vec![];
+    read_lines(path, 0, &mut lines)?;
+
+    // First pass: every statement's size is known without evaluating anything
+    let mut symbols = HashMap::new();
+    let mut address = PROGRAM_OFFSET as usize;
+    for line in lines.iter() {
+        let (label, statement) = split_label(&line.text);
+        if let Some(label) = label {
+            define(&mut symbols, label, Symbol::Label(address as u16))
+                .map_err(|e| line.error(e))?;
+        }
+        let (mnemonic, operands) = split_statement(statement);
+        if mnemonic.is_empty() {
+            continue;
+        }
+        if let Some((name, value)) = constant(statement) {
+            define(&mut symbols, name, Symbol::Constant(value.to_string()))
+                .map_err(|e| line.error(e))?;
+            continue;
+        }
+        address += size(&mnemonic, &operands);
+        if address > 0x10000 {
+            return Err(line.error(String::from("program does not fit in memory")));
+        }
+    }
+
+    // Second pass: every name is known, statements are encoded
+    let assembler = Assembler { symbols: &symbols };
+    let mut rom = vec![];
+    for line in lines.iter() {
+        let (_, statement) = split_label(&line.text);
+        let (mnemonic, operands) = split_statement(statement);
+        if mnemonic.is_empty() || constant(statement).is_some() {
+            continue;
+        }
+        let bytes = assembler
+            .statement(&mnemonic, &operands)
+            .map_err(|e| line.error(e))?;
+        rom.extend(bytes);
+    }
+    Ok(rom)
+}
+
+fn read_lines(path: &Path, depth: usize, lines: &mut Vec<Line>) -> Result<(), String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+    for (index, text) in source.lines().enumerate() {
+        let line = Line {
+            file: path.to_path_buf(),
+            number: index + 1,
+            text: strip_comment(text).trim().to_string(),
+        };
+        let (mnemonic, operands) = split_statement(&line.text);
+        if !mnemonic.eq_ignore_ascii_case("include") {
+            lines.push(line);
+            continue;
+        }
+        if depth == MAX_INCLUDE_DEPTH {
+            return Err(line.error(String::from("includes nest too deep")));
+        }
+        let file = match operands.as_slice() {
+            [file] => parse_string(file)
+                .ok_or_else(|| line.error(String::from("expected a quoted file name")))?,
+            _ => return Err(line.error(String::from("expected a quoted file name"))),
+        };
+        // Relative to the including file
+        let included = path.parent().unwrap_or_else(|| Path::new("")).join(file);
+        read_lines(&included, depth + 1, lines)?;
+    }
+    Ok(())
+}
+
+// `;` outside of a string starts a comment
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..index],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn split_label(text: &str) -> (Option<&str>, &str) {
+    let first = text.split_whitespace().next().unwrap_or("");
+    match first.strip_suffix(':') {
+        Some(label) if !label.is_empty() => (Some(label), text[first.len()..].trim()),
+        _ => (None, text),
+    }
+}
+
+// The mnemonic, in upper case, and the comma separated operands
+fn split_statement(statement: &str) -> (String, Vec<String>) {
+    let statement = statement.trim();
+    let (mnemonic, rest) = statement
+        .split_once(char::is_whitespace)
+        .unwrap_or((statement, ""));
+    let mut operands = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in rest.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c)
+            }
+            ',' if !quoted => operands.push(std::mem::take(&mut current).trim().to_string()),
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    (mnemonic.to_ascii_uppercase(), operands)
+}
+
+// `name equ value`
+fn constant(statement: &str) -> Option<(&str, &str)> {
+    let mut words = statement.splitn(3, char::is_whitespace);
+    let name = words.next()?;
+    let keyword = words.next()?;
+    if !keyword.eq_ignore_ascii_case("equ") {
+        return None;
+    }
+    Some((name, words.next()?.trim()))
+}
+
+fn define(symbols: &mut HashMap<String, Symbol>, name: &str, symbol: Symbol) -> Result<(), String> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid || register(name).is_some() || keyword(name) {
+        return Err(format!("{} can't be used as a name", name));
+    }
+    if symbols.insert(name.to_string(), symbol).is_some() {
+        return Err(format!("{} is defined twice", name));
+    }
+    Ok(())
+}
+
+fn keyword(name: &str) -> bool {
+    ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"]
+        .iter()
+        .any(|keyword| name.eq_ignore_ascii_case(keyword))
+}
+
+fn register(operand: &str) -> Option<u8> {
+    let digit = operand.strip_prefix(['V', 'v'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn parse_string(operand: &str) -> Option<&str> {
+    operand.strip_prefix('"')?.strip_suffix('"')
+}
+
+fn size(mnemonic: &str, operands: &[String]) -> usize {
+    match mnemonic {
+        "DB" => operands
+            .iter()
+            .map(|operand| parse_string(operand).map_or(1, str::len))
+            .sum(),
+        "DW" => 2 * operands.len(),
+        "LD" if operands
+            .get(1)
+            .is_some_and(|operand| long(operand).is_some()) =>
+        {
+            4
+        }
+        _ => 2,
+    }
+}
+
+// `LONG address`, the operand of the 4 byte F000 nnnn
+fn long(operand: &str) -> Option<&str> {
+    let (keyword, address) = operand.split_once(char::is_whitespace)?;
+    if keyword.eq_ignore_ascii_case("long") {
+        Some(address.trim())
+    } else {
+        None
+    }
+}
+
+struct Assembler<'a> {
+    symbols: &'a HashMap<String, Symbol>,
+}
+
+impl<'a> Assembler<'a> {
+    // Sums and differences of numbers, labels and constants
+    fn evaluate(&self, expression: &str, depth: usize) -> Result<i64, String> {
+        if depth > MAX_CONSTANT_DEPTH {
+            return Err(format!("{} refers to itself", expression));
+        }
+        let mut total = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+        let mut terms = vec![];
+        for c in expression.chars().filter(|c| !c.is_whitespace()) {
+            match c {
+                '+' | '-' if !term.is_empty() => {
+                    terms.push((sign, std::mem::take(&mut term)));
+                    sign = if c == '-' { -1 } else { 1 };
+                }
+                '-' => sign = -sign,
+                '+' => {}
+                _ => term.push(c),
+            }
+        }
+        if term.is_empty() {
+            return Err(format!("incomplete expression {}", expression));
+        }
+        terms.push((sign, term));
+        for (sign, term) in terms {
+            total += sign * self.term(&term, depth)?;
+        }
+        Ok(total)
+    }
+
+    fn term(&self, term: &str, depth: usize) -> Result<i64, String> {
+        let lower = term.to_ascii_lowercase();
+        let number = if let Some(digits) = lower.strip_prefix("0x") {
+            i64::from_str_radix(digits, 16).ok()
+        } else if let Some(digits) = lower.strip_prefix("0b") {
+            i64::from_str_radix(digits, 2).ok()
+        } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+            term.parse().ok()
+        } else {
+            match self.symbols.get(term) {
+                Some(Symbol::Label(address)) => return Ok(*address as i64),
+                Some(Symbol::Constant(value)) => return self.evaluate(value, depth + 1),
+                None => return Err(format!("unknown name {}", term)),
+            }
+        };
+        number.ok_or_else(|| format!("invalid number {}", term))
+    }
+
+    // Values from `-(max + 1) / 2` up to `max`, negative ones wrap around
+    fn value(&self, operand: &str, max: i64) -> Result<u16, String> {
+        let value = self.evaluate(operand, 0)?;
+        if value > max || value < -(max + 1) / 2 {
+            return Err(format!("{} does not fit in 0x{:x}", operand, max));
+        }
+        Ok((value & max) as u16)
+    }
+
+    fn address(&self, operand: &str) -> Result<u16, String> {
+        self.value(operand, 0xfff)
+    }
+
+    fn byte(&self, operand: &str) -> Result<u8, String> {
+        Ok(self.value(operand, 0xff)? as u8)
+    }
+
+    fn nibble(&self, operand: &str) -> Result<u8, String> {
+        Ok(self.value(operand, 0xf)? as u8)
+    }
+
+    fn statement(&self, mnemonic: &str, operands: &[String]) -> Result<Vec<u8>, String> {
+        match mnemonic {
+            "DB" => {
+                let mut bytes = vec![];
+                for operand in operands {
+                    match parse_string(operand) {
+                        Some(string) => bytes.extend(string.bytes()),
+                        None => bytes.push(self.byte(operand)?),
+                    }
+                }
+                Ok(bytes)
+            }
+            "DW" => {
+                let mut bytes = vec![];
+                for operand in operands {
+                    bytes.extend(self.value(operand, 0xffff)?.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            _ => Ok(self.instruction(mnemonic, operands)?.encode()),
+        }
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[String]) -> Result<Opcode, String> {
+        let operands: Vec<&str> = operands.iter().map(String::as_str).collect();
+        let v = |operand: &str| {
+            register(operand)
+                .map(|id| Register::new(id as u16).unwrap())
+                .ok_or_else(|| format!("expected a register, got {}", operand))
+        };
+        let is = |operand: &str, keyword: &str| operand.eq_ignore_ascii_case(keyword);
+        let opcode = match (mnemonic, operands.as_slice()) {
+            ("CLS", []) => Opcode::CLS,
+            ("RET", []) => Opcode::RET,
+            ("SCR", []) => Opcode::SCR,
+            ("SCL", []) => Opcode::SCL,
+            ("EXIT", []) => Opcode::EXIT,
+            ("LOW", []) => Opcode::LOW,
+            ("HIGH", []) => Opcode::HIGH,
+            ("AUDIO", []) => Opcode::AUDIO,
+            ("SYS", [address]) => Opcode::SYS(self.address(address)?),
+            ("SCD", [n]) => Opcode::SCD(self.nibble(n)?),
+            ("SCU", [n]) => Opcode::SCU(self.nibble(n)?),
+            ("PLANE", [n]) => Opcode::PLANE(self.nibble(n)?),
+            ("JP", [v0, address]) if is(v0, "V0") => Opcode::JPA(self.address(address)?),
+            ("JP", [address]) => Opcode::JP(self.address(address)?),
+            ("CALL", [address]) => Opcode::CALL(self.address(address)?),
+            ("SE", [x, y]) if register(y).is_some() => Opcode::SE(v(x)?, v(y)?),
+            ("SE", [x, byte]) => Opcode::SEI(v(x)?, self.byte(byte)?),
+            ("SNE", [x, y]) if register(y).is_some() => Opcode::SNE(v(x)?, v(y)?),
+            ("SNE", [x, byte]) => Opcode::SNEI(v(x)?, self.byte(byte)?),
+            ("SAVE", [range]) | ("LOAD", [range]) => {
+                let (x, y) = range
+                    .split_once('-')
+                    .ok_or_else(|| String::from("expected a register range like V1 - V3"))?;
+                let (x, y) = (v(x.trim())?, v(y.trim())?);
+                if mnemonic == "SAVE" {
+                    Opcode::SAVE(x, y)
+                } else {
+                    Opcode::LOAD(x, y)
+                }
+            }
+            ("OR", [x, y]) => Opcode::OR(v(x)?, v(y)?),
+            ("AND", [x, y]) => Opcode::AND(v(x)?, v(y)?),
+            ("XOR", [x, y]) => Opcode::XOR(v(x)?, v(y)?),
+            ("SUB", [x, y]) => Opcode::SUBR(v(x)?, v(y)?),
+            ("SUBN", [x, y]) => Opcode::SUBN(v(x)?, v(y)?),
+            ("SHR", [x]) => Opcode::SHR(v(x)?, v(x)?),
+            ("SHR", [x, y]) => Opcode::SHR(v(x)?, v(y)?),
+            ("SHL", [x]) => Opcode::SHL(v(x)?, v(x)?),
+            ("SHL", [x, y]) => Opcode::SHL(v(x)?, v(y)?),
+            ("ADD", [i, x]) if is(i, "I") => Opcode::ADDRI(v(x)?),
+            ("ADD", [x, y]) if register(y).is_some() => Opcode::ADD(v(x)?, v(y)?),
+            ("ADD", [x, byte]) => Opcode::ADDI(v(x)?, self.byte(byte)?),
+            ("RND", [x, byte]) => Opcode::RND(v(x)?, self.byte(byte)?),
+            ("DRW", [x, y, n]) => Opcode::DRW(v(x)?, v(y)?, self.nibble(n)?),
+            ("SKP", [x]) => Opcode::SKP(v(x)?),
+            ("SKNP", [x]) => Opcode::SKNP(v(x)?),
+            ("PITCH", [x]) => Opcode::PITCH(v(x)?),
+            ("LD", [target, source]) => self.load(target, source)?,
+            _ => {
+                return Err(format!(
+                    "unknown instruction {} {}",
+                    mnemonic,
+                    operands.join(", ")
+                ))
+            }
+        };
+        Ok(opcode)
+    }
+
+    // The many forms of LD
+    fn load(&self, target: &str, source: &str) -> Result<Opcode, String> {
+        let v = |operand: &str| register(operand).map(|id| Register::new(id as u16).unwrap());
+        let is = |operand: &str, keyword: &str| operand.eq_ignore_ascii_case(keyword);
+        if let Some(x) = v(target) {
+            return Ok(if let Some(y) = v(source) {
+                Opcode::LD(x, y)
+            } else if is(source, "DT") {
+                Opcode::LDVDT(x)
+            } else if is(source, "K") {
+                Opcode::LDK(x)
+            } else if is(source, "[I]") {
+                Opcode::LDMI(x)
+            } else if is(source, "R") {
+                Opcode::LDVR(x)
+            } else {
+                Opcode::LDI(x, self.byte(source)?)
+            });
+        }
+        if is(target, "I") {
+            return Ok(match long(source) {
+                Some(address) => Opcode::LDIL(self.value(address, 0xffff)?),
+                None => Opcode::LDII(self.address(source)?),
+            });
+        }
+        let x = v(source).ok_or_else(|| format!("expected a register, got {}", source))?;
+        Ok(match target.to_ascii_uppercase().as_str() {
+            "DT" => Opcode::LDDTV(x),
+            "ST" => Opcode::LDST(x),
+            "F" => Opcode::LDF(x),
+            "HF" => Opcode::LDHF(x),
+            "B" => Opcode::LDB(x),
+            "[I]" => Opcode::LDIM(x),
+            "R" => Opcode::LDRV(x),
+            _ => return Err(format!("unknown instruction LD {}, {}", target, source)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::{disassemble, Syntax};
+    use chip_8::engine::mode::Mode;
+    use std::fs;
+
+    // Writes each (name, source) into a fresh directory and assembles the first
+    fn assemble_files(test: &str, files: &[(&str, &str)]) -> Result<Vec<u8>, String> {
+        let directory =
+            std::env::temp_dir().join(format!("chip-8-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for (name, source) in files {
+            fs::write(directory.join(name), source).unwrap();
+        }
+        let rom = assemble(&directory.join(files[0].0));
+        fs::remove_dir_all(&directory).unwrap();
+        rom
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms");
+        for entry in fs::read_dir(roms).unwrap() {
+            let path = entry.unwrap().path();
+            let rom = fs::read(&path).unwrap();
+            let listing = disassemble(&rom, Mode::Chip8, Syntax::Mnemonic);
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let assembled = assemble_files(name, &[("rom.asm", &listing)]);
+            assert_eq!(assembled.as_ref(), Ok(&rom), "{}", name);
+        }
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "SPEED equ 3
+main:   LD V0, SPEED + 1 ; comment
+        LD I, sprite
+loop:   JP loop
+sprite: db 0xf0, 0b1001, \"A;\"
+        dw 0x1234, sprite - main
+";
+        let rom = assemble_files("labels", &[("main.asm", source)]).unwrap();
+        assert_eq!(
+            rom,
+            [0x60, 0x04, 0xa2, 0x06, 0x12, 0x04, 0xf0, 0x09, b'A', b';', 0x12, 0x34, 0x00, 0x06]
+        );
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_file() {
+        let files = [
+            ("main.asm", "JP data\ninclude \"data.asm\""),
+            ("data.asm", "data: db 1"),
+        ];
+        assert_eq!(
+            assemble_files("include", &files),
+            Ok(vec![0x12, 0x02, 0x01])
+        );
+    }
+
+    #[test]
+    fn errors_name_the_file_and_line() {
+        let error = assemble_files("error", &[("main.asm", "CLS\nJP nowhere")]).unwrap_err();
+        assert!(
+            error.ends_with("main.asm:2: unknown name nowhere"),
+            "{}",
+            error
+        );
+
+        let error = assemble_files("cycle", &[("main.asm", "include \"main.asm\"")]).unwrap_err();
+        assert!(error.ends_with("includes nest too deep"), "{}", error);
+    }
+}
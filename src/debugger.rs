@@ -0,0 +1,348 @@
+use crate::expression::Expression;
+use chip_8::engine::error::EmulatorError;
+use chip_8::engine::key::Key;
+use chip_8::engine::memory::Watchpoint;
+use chip_8::engine::opcode::Opcode;
+use chip_8::engine::vm::VM;
+use std::collections::BTreeMap;
+
+const HELP: &str = "\
+break <addr> [if <cond>]  stop before running the instruction at addr (b)
+when <cond>               stop when cond becomes true, e.g. V3 == 0x10 && I > 0x300
+watch <addr> [end]        stop when the ROM writes to memory in addr..=end
+rwatch <addr> [end]       stop when the ROM reads memory in addr..=end
+awatch <addr> [end]       stop when the ROM reads or writes memory in addr..=end
+delete [addr | #n]        remove a breakpoint, a condition, or everything (d)
+unwatch <addr>            remove the watchpoints starting at addr
+breakpoints               list breakpoints, conditions and watchpoints
+continue                  resume emulation (c)
+pause                     stop emulation (p)
+step [n]                  run n instructions, 1 by default (s)
+next                      run to the instruction after the current one, over CALLs (n)
+registers                 print the registers, I, DT, ST, PC and the stack (r)
+stack                     print the return addresses, innermost last
+mem <addr> [len]          hex dump memory, 64 bytes by default (x)
+disasm [addr] [n]         disassemble n instructions from addr, around PC by default (u)
+help                      print this help (h)
+Addresses and lengths are hexadecimal. Conditions use V0-VF, I, PC, SP, DT, ST,
+[addr] for a byte of memory, decimal or 0x prefixed numbers and the operators
+|| && | & == != < <= > >= + - !";
+
+// Runs the VM one instruction at a time so it can stop at breakpoints. Commands
+// come from a terminal REPL while the window keeps showing the screen.
+pub struct Debugger {
+    // Breakpoints only stop when their condition, if any, holds
+    breakpoints: BTreeMap<u16, Option<Expression>>,
+    // Conditions along with whether they held after the last instruction
+    conditions: Vec<(Expression, bool)>,
+    watchpoints: Vec<Watchpoint>,
+    paused: bool,
+    // Set by `next` on a CALL: the return address and the stack depth to return to
+    step_over: Option<(u16, usize)>,
+}
+
+impl Debugger {
+    // Starts paused so breakpoints can be set before the ROM runs
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeMap::new(),
+            conditions: vec![],
+            watchpoints: vec![],
+            paused: true,
+            step_over: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn prompt(&self, vm: &VM) {
+        println!("Paused at {}", location(vm, vm.cpu().program_counter()));
+    }
+
+    // Like `VM::run_frame`, but stops in the middle of the frame when a
+    // breakpoint is reached, in which case timers are not ticked
+    pub fn run_frame(
+        &mut self,
+        vm: &mut VM,
+        keys: &[Key],
+        instructions_per_frame: usize,
+    ) -> Result<(), EmulatorError> {
+        for _ in 0..instructions_per_frame {
+            if vm.is_halted() {
+                break;
+            }
+            vm.step_instruction(keys)?;
+            if self.should_stop(vm) {
+                self.paused = true;
+                self.prompt(vm);
+                return Ok(());
+            }
+        }
+        vm.tick_timers();
+        Ok(())
+    }
+
+    // Checked after every instruction, every condition is evaluated so that
+    // they all notice when they become true
+    fn should_stop(&mut self, vm: &VM) -> bool {
+        let program_counter = vm.cpu().program_counter();
+        let mut stop = false;
+        if let Some(hit) = vm.take_watch_hit() {
+            if hit.write {
+                println!("Watchpoint: wrote {:02x} to {:04x}", hit.value, hit.address);
+            } else {
+                println!(
+                    "Watchpoint: read {:02x} from {:04x}",
+                    hit.value, hit.address
+                );
+            }
+            stop = true;
+        }
+        for (condition, held) in self.conditions.iter_mut() {
+            let holds = condition.is_true(vm);
+            if holds && !*held {
+                println!("Condition became true: {}", condition);
+                stop = true;
+            }
+            *held = holds;
+        }
+        if let Some(condition) = self.breakpoints.get(&program_counter) {
+            if condition
+                .as_ref()
+                .is_none_or(|condition| condition.is_true(vm))
+            {
+                println!("Breakpoint at {:04x}", program_counter);
+                stop = true;
+            }
+        }
+        if let Some((address, depth)) = self.step_over {
+            if program_counter == address && vm.cpu().stack().len() <= depth {
+                stop = true;
+            }
+        }
+        if stop {
+            self.step_over = None;
+        }
+        stop
+    }
+
+    pub fn command(&mut self, vm: &mut VM, keys: &[Key], line: &str) -> Result<(), EmulatorError> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let result = match command {
+            "break" | "b" => self.add_breakpoint(rest),
+            "when" => Expression::parse(rest).map(|condition| {
+                let holds = condition.is_true(vm);
+                self.conditions.push((condition, holds));
+            }),
+            "delete" | "d" if rest.trim().starts_with('#') => {
+                let index = rest.trim()[1..].parse::<usize>().ok();
+                match index.filter(|index| *index < self.conditions.len()) {
+                    Some(index) => {
+                        self.conditions.remove(index);
+                        Ok(())
+                    }
+                    None => Err(format!("No condition {}", rest.trim())),
+                }
+            }
+            "watch" | "rwatch" | "awatch" => parse_numbers(rest).and_then(|numbers| {
+                let (start, end) = match numbers.as_slice() {
+                    [start] => (*start, *start),
+                    [start, end] if start <= end => (*start, *end),
+                    _ => return Err(String::from("Expected an address or a range")),
+                };
+                self.watchpoints.push(Watchpoint {
+                    start,
+                    end,
+                    reads: command != "watch",
+                    writes: command != "rwatch",
+                });
+                vm.set_watchpoints(self.watchpoints.clone());
+                Ok(())
+            }),
+            _ => match parse_numbers(rest) {
+                Ok(numbers) => return self.inspect(vm, keys, command, &numbers),
+                Err(e) => Err(e),
+            },
+        };
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+        Ok(())
+    }
+
+    // `break <addr>` or `break <addr> if <condition>`
+    fn add_breakpoint(&mut self, arguments: &str) -> Result<(), String> {
+        let (address, condition) = match arguments.split_once(" if ") {
+            Some((address, condition)) => (address, Some(Expression::parse(condition)?)),
+            None => (arguments, None),
+        };
+        let address = parse_hex(address.trim())?;
+        match &condition {
+            Some(condition) => println!("Breakpoint set at {:04x} if {}", address, condition),
+            None => println!("Breakpoint set at {:04x}", address),
+        }
+        self.breakpoints.insert(address, condition);
+        Ok(())
+    }
+
+    // Commands that only take hexadecimal numbers
+    fn inspect(
+        &mut self,
+        vm: &mut VM,
+        keys: &[Key],
+        command: &str,
+        numbers: &[u16],
+    ) -> Result<(), EmulatorError> {
+        match (command, numbers) {
+            ("delete" | "d", []) => {
+                self.breakpoints.clear();
+                self.conditions.clear();
+                self.watchpoints.clear();
+                vm.set_watchpoints(vec![]);
+            }
+            ("delete" | "d", [address]) => {
+                if self.breakpoints.remove(address).is_none() {
+                    println!("No breakpoint at {:04x}", address)
+                }
+            }
+            ("unwatch", [address]) => {
+                self.watchpoints
+                    .retain(|watchpoint| watchpoint.start != *address);
+                vm.set_watchpoints(self.watchpoints.clone());
+            }
+            ("breakpoints", []) => {
+                for (address, condition) in self.breakpoints.iter() {
+                    match condition {
+                        Some(condition) => println!("{} if {}", location(vm, *address), condition),
+                        None => println!("{}", location(vm, *address)),
+                    }
+                }
+                for (index, (condition, _)) in self.conditions.iter().enumerate() {
+                    println!("#{} when {}", index, condition)
+                }
+                for watchpoint in self.watchpoints.iter() {
+                    let access = match (watchpoint.reads, watchpoint.writes) {
+                        (true, true) => "reads and writes",
+                        (true, false) => "reads",
+                        _ => "writes",
+                    };
+                    println!(
+                        "{:04x}..={:04x} {}",
+                        watchpoint.start, watchpoint.end, access
+                    )
+                }
+            }
+            ("continue" | "c", []) => self.paused = false,
+            ("pause" | "p", []) => {
+                self.paused = true;
+                self.prompt(vm)
+            }
+            ("step" | "s", []) => self.step(vm, keys, 1)?,
+            ("step" | "s", [count]) => self.step(vm, keys, *count)?,
+            ("next" | "n", []) => {
+                let program_counter = vm.cpu().program_counter();
+                match vm.cpu().instruction_at(vm.memory(), program_counter) {
+                    Ok((Opcode::CALL(_), length)) => {
+                        self.step_over =
+                            Some((program_counter.wrapping_add(length), vm.cpu().stack().len()));
+                        self.paused = false
+                    }
+                    _ => self.step(vm, keys, 1)?,
+                }
+            }
+            ("registers" | "r", []) => println!("{}", vm.cpu()),
+            ("stack", []) => {
+                for (depth, address) in vm.cpu().stack().iter().enumerate() {
+                    println!("#{} {:04x}", depth, address)
+                }
+            }
+            ("mem" | "x", [address]) => hexdump(vm, *address, 0x40),
+            ("mem" | "x", [address, length]) => hexdump(vm, *address, *length),
+            ("disasm" | "u", []) => {
+                let program_counter = vm.cpu().program_counter();
+                disassemble(vm, program_counter.saturating_sub(8), 10)
+            }
+            ("disasm" | "u", [address]) => disassemble(vm, *address, 10),
+            ("disasm" | "u", [address, count]) => disassemble(vm, *address, *count),
+            ("help" | "h", []) => println!("{}", HELP),
+            _ => println!("Unknown command, type help for a list"),
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, vm: &mut VM, keys: &[Key], count: u16) -> Result<(), EmulatorError> {
+        for _ in 0..count {
+            vm.step_instruction(keys)?;
+            if self.should_stop(vm) {
+                break;
+            }
+        }
+        self.paused = true;
+        self.prompt(vm);
+        Ok(())
+    }
+}
+
+fn parse_numbers(arguments: &str) -> Result<Vec<u16>, String> {
+    arguments.split_whitespace().map(parse_hex).collect()
+}
+
+fn parse_hex(word: &str) -> Result<u16, String> {
+    let digits = word.trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Not a hexadecimal number: {}", word))
+}
+
+// Address followed by the instruction there
+fn location(vm: &VM, address: u16) -> String {
+    match vm.cpu().instruction_at(vm.memory(), address) {
+        Ok((opcode, _)) => format!("{:04x}  {}", address, opcode),
+        Err(_) => format!("{:04x}", address),
+    }
+}
+
+fn hexdump(vm: &VM, address: u16, length: u16) {
+    let bytes: Vec<u8> = (0..length)
+        .map_while(|offset| vm.memory().peek(address.checked_add(offset)?).ok())
+        .collect();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|byte| match byte {
+                0x20..=0x7e => *byte as char,
+                _ => '.',
+            })
+            .collect();
+        println!(
+            "{:04x}  {:<47}  {}",
+            address as usize + row * 16,
+            hex.join(" "),
+            ascii
+        )
+    }
+}
+
+fn disassemble(vm: &VM, mut address: u16, count: u16) {
+    let program_counter = vm.cpu().program_counter();
+    for _ in 0..count {
+        let marker = if address == program_counter { '>' } else { ' ' };
+        let length = match vm.cpu().instruction_at(vm.memory(), address) {
+            Ok((opcode, length)) => {
+                println!("{} {:04x}  {}", marker, address, opcode);
+                length
+            }
+            Err(EmulatorError::UnknownOpcode { instruction, .. }) => {
+                println!("{} {:04x}  {:04x}", marker, address, instruction);
+                2
+            }
+            Err(_) => break,
+        };
+        address = match address.checked_add(length) {
+            Some(address) => address,
+            None => break,
+        };
+    }
+}
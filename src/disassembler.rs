@@ -0,0 +1,307 @@
+use chip_8::engine::cpu::CPU;
+use chip_8::engine::mode::Mode;
+use chip_8::engine::opcode::Opcode;
+use chip_8::engine::register::Register;
+use chip_8::engine::vm::PROGRAM_OFFSET;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Syntax {
+    // The mnemonics of `Opcode`'s Display, which `asm` reads back
+    Mnemonic,
+    Octo,
+}
+
+// Ordered by precedence, an address called and jumped to is a subroutine
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+enum Label {
+    Data,
+    Jump,
+    Subroutine,
+}
+
+const DATA_PER_LINE: usize = 8;
+
+struct Disassembly<'a> {
+    rom: &'a [u8],
+    mode: Mode,
+    // Start of every instruction reachable from the entry point
+    code: BTreeSet<u16>,
+    // Bytes covered by those instructions
+    covered: BTreeSet<u16>,
+    labels: BTreeMap<u16, Label>,
+}
+
+// Decodes a ROM loaded at 0x200, following jumps, calls and skips from the
+// entry point to tell code from data. Everything that isn't reached is
+// printed as data, and addresses that are jumped to, called or loaded into I
+// get labels.
+pub fn disassemble(rom: &[u8], mode: Mode, syntax: Syntax) -> String {
+    let mut disassembly = Disassembly {
+        rom,
+        mode,
+        code: BTreeSet::new(),
+        covered: BTreeSet::new(),
+        labels: BTreeMap::new(),
+    };
+    disassembly.analyse();
+    disassembly.print(syntax)
+}
+
+impl<'a> Disassembly<'a> {
+    fn end(&self) -> u32 {
+        PROGRAM_OFFSET as u32 + self.rom.len() as u32
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        address >= PROGRAM_OFFSET && (address as u32) < self.end()
+    }
+
+    fn byte(&self, address: u16) -> Option<u8> {
+        if !self.contains(address) {
+            return None;
+        }
+        Some(self.rom[(address - PROGRAM_OFFSET) as usize])
+    }
+
+    fn word(&self, address: u16) -> Option<u16> {
+        let high = self.byte(address)?;
+        let low = self.byte(address.checked_add(1)?)?;
+        Some((high as u16) << 8 | low as u16)
+    }
+
+    // Only the 4 byte F000 nnnn of XO-CHIP is longer than 2 bytes
+    fn instruction_length(&self, address: u16) -> u16 {
+        if self.mode == Mode::XoChip && self.word(address) == Some(0xf000) {
+            4
+        } else {
+            2
+        }
+    }
+
+    fn decode(&self, address: u16) -> Option<(Opcode, u16)> {
+        let instruction = self.word(address)?;
+        let length = self.instruction_length(address);
+        // Keeps `address + length` in range at the very end of XO-CHIP memory
+        address.checked_add(length)?;
+        let operand = if length == 4 {
+            self.word(address.checked_add(2)?)?
+        } else {
+            0
+        };
+        let opcode = CPU::decode(self.mode, address, instruction, operand).ok()?;
+        Some((opcode, length))
+    }
+
+    fn label(&mut self, address: u16, label: Label) {
+        if self.contains(address) {
+            let current = self.labels.entry(address).or_insert(label);
+            *current = (*current).max(label);
+        }
+    }
+
+    fn analyse(&mut self) {
+        let mut pending = vec![PROGRAM_OFFSET];
+        self.label(PROGRAM_OFFSET, Label::Subroutine);
+        while let Some(address) = pending.pop() {
+            if self.covered.contains(&address) {
+                continue;
+            }
+            let (opcode, length) = match self.decode(address) {
+                Some(decoded) => decoded,
+                None => continue,
+            };
+            // Instructions that would overlap one already decoded are left as data
+            if (address..address + length).any(|byte| self.covered.contains(&byte)) {
+                continue;
+            }
+            self.code.insert(address);
+            self.covered.extend(address..address + length);
+            let next = address + length;
+            match opcode {
+                Opcode::JP(target) => {
+                    self.label(target, Label::Jump);
+                    pending.push(target);
+                }
+                Opcode::CALL(target) => {
+                    self.label(target, Label::Subroutine);
+                    pending.push(target);
+                    pending.push(next);
+                }
+                // The target depends on V0, only the base gets a label
+                Opcode::JPA(target) => self.label(target, Label::Jump),
+                Opcode::RET | Opcode::EXIT => {}
+                Opcode::SEI(..)
+                | Opcode::SNEI(..)
+                | Opcode::SE(..)
+                | Opcode::SNE(..)
+                | Opcode::SKP(_)
+                | Opcode::SKNP(_) => {
+                    pending.push(next);
+                    pending.push(next.wrapping_add(self.instruction_length(next)));
+                }
+                Opcode::LDII(target) | Opcode::LDIL(target) => {
+                    self.label(target, Label::Data);
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+        // Labels in the middle of an instruction have nowhere to go
+        let code = &self.code;
+        let covered = &self.covered;
+        self.labels
+            .retain(|address, _| code.contains(address) || !covered.contains(address));
+    }
+
+    fn name(&self, address: u16) -> String {
+        match self.labels.get(&address) {
+            Some(_) if address == PROGRAM_OFFSET => String::from("main"),
+            Some(Label::Subroutine) => format!("sub_{:03x}", address),
+            Some(Label::Jump) => format!("label_{:03x}", address),
+            Some(Label::Data) => format!("data_{:03x}", address),
+            None => format!("0x{:03x}", address),
+        }
+    }
+
+    fn print(&self, syntax: Syntax) -> String {
+        let comment = match syntax {
+            Syntax::Mnemonic => ';',
+            Syntax::Octo => '#',
+        };
+        let mut output = String::new();
+        let mut address = PROGRAM_OFFSET as u32;
+        while address < self.end() {
+            let current = address as u16;
+            if self.labels.contains_key(&current) {
+                match syntax {
+                    Syntax::Mnemonic => output.push_str(&format!("{}:\n", self.name(current))),
+                    Syntax::Octo => output.push_str(&format!(": {}\n", self.name(current))),
+                }
+            }
+            let (text, length) = match self.decode(current) {
+                Some((opcode, length)) if self.code.contains(&current) => {
+                    let text = match syntax {
+                        Syntax::Mnemonic => self.mnemonic(&opcode),
+                        Syntax::Octo => self.octo(&opcode),
+                    };
+                    (text, length as u32)
+                }
+                _ => {
+                    let length = self.data_length(current);
+                    let bytes: Vec<String> = (0..length)
+                        .map(|offset| format!("0x{:02x}", self.rom_byte(address + offset)))
+                        .collect();
+                    let text = match syntax {
+                        Syntax::Mnemonic => format!("db {}", bytes.join(", ")),
+                        Syntax::Octo => bytes.join(" "),
+                    };
+                    (text, length)
+                }
+            };
+            let raw: Vec<String> = (0..length)
+                .map(|offset| format!("{:02x}", self.rom_byte(address + offset)))
+                .collect();
+            output.push_str(&format!(
+                "    {:<32} {} {:04x}  {}\n",
+                text,
+                comment,
+                address,
+                raw.join(" ")
+            ));
+            address += length;
+        }
+        output
+    }
+
+    fn rom_byte(&self, address: u32) -> u8 {
+        self.rom[(address - PROGRAM_OFFSET as u32) as usize]
+    }
+
+    // Data runs end at the next instruction, label or line break
+    fn data_length(&self, address: u16) -> u32 {
+        let mut length = 1;
+        while length < DATA_PER_LINE as u32 && (address as u32 + length) < self.end() {
+            let next = address + length as u16;
+            if self.code.contains(&next) || self.labels.contains_key(&next) {
+                break;
+            }
+            length += 1;
+        }
+        length
+    }
+
+    fn mnemonic(&self, opcode: &Opcode) -> String {
+        match opcode {
+            Opcode::JP(address) => format!("JP {}", self.name(*address)),
+            Opcode::CALL(address) => format!("CALL {}", self.name(*address)),
+            Opcode::LDII(address) => format!("LD I, {}", self.name(*address)),
+            Opcode::LDIL(address) => format!("LD I, LONG {}", self.name(*address)),
+            Opcode::JPA(address) => format!("JP V0, {}", self.name(*address)),
+            _ => opcode.to_string(),
+        }
+    }
+
+    fn octo(&self, opcode: &Opcode) -> String {
+        let v = |register: &Register| format!("v{:x}", register.id);
+        match opcode {
+            // Octo has no SYS, the instruction is written out as bytes
+            Opcode::SYS(address) => format!("0x{:02x} 0x{:02x}", address >> 8, address & 0xff),
+            Opcode::CLS => String::from("clear"),
+            Opcode::RET => String::from("return"),
+            Opcode::SCD(n) => format!("scroll-down {}", n),
+            Opcode::SCU(n) => format!("scroll-up {}", n),
+            Opcode::SCR => String::from("scroll-right"),
+            Opcode::SCL => String::from("scroll-left"),
+            Opcode::EXIT => String::from("exit"),
+            Opcode::LOW => String::from("lores"),
+            Opcode::HIGH => String::from("hires"),
+            Opcode::JP(address) => format!("jump {}", self.name(*address)),
+            Opcode::CALL(address) => match self.labels.get(address) {
+                Some(_) => self.name(*address),
+                None => format!(":call {}", self.name(*address)),
+            },
+            // Octo conditions say when the next instruction runs, the
+            // opposite of when it gets skipped
+            Opcode::SEI(x, byte) => format!("if {} != 0x{:02x} then", v(x), byte),
+            Opcode::SNEI(x, byte) => format!("if {} == 0x{:02x} then", v(x), byte),
+            Opcode::SE(x, y) => format!("if {} != {} then", v(x), v(y)),
+            Opcode::SNE(x, y) => format!("if {} == {} then", v(x), v(y)),
+            Opcode::SKP(x) => format!("if {} -key then", v(x)),
+            Opcode::SKNP(x) => format!("if {} key then", v(x)),
+            Opcode::SAVE(x, y) => format!("save {} - {}", v(x), v(y)),
+            Opcode::LOAD(x, y) => format!("load {} - {}", v(x), v(y)),
+            Opcode::LDI(x, byte) => format!("{} := 0x{:02x}", v(x), byte),
+            Opcode::ADDI(x, byte) => format!("{} += 0x{:02x}", v(x), byte),
+            Opcode::LD(x, y) => format!("{} := {}", v(x), v(y)),
+            Opcode::OR(x, y) => format!("{} |= {}", v(x), v(y)),
+            Opcode::AND(x, y) => format!("{} &= {}", v(x), v(y)),
+            Opcode::XOR(x, y) => format!("{} ^= {}", v(x), v(y)),
+            Opcode::ADD(x, y) => format!("{} += {}", v(x), v(y)),
+            Opcode::SUBR(x, y) => format!("{} -= {}", v(x), v(y)),
+            Opcode::SHR(x, y) => format!("{} >>= {}", v(x), v(y)),
+            Opcode::SUBN(x, y) => format!("{} =- {}", v(x), v(y)),
+            Opcode::SHL(x, y) => format!("{} <<= {}", v(x), v(y)),
+            Opcode::LDII(address) => format!("i := {}", self.name(*address)),
+            Opcode::LDIL(address) => format!("i := long {}", self.name(*address)),
+            Opcode::PLANE(n) => format!("plane {}", n),
+            Opcode::AUDIO => String::from("audio"),
+            Opcode::JPA(address) => format!("jump0 {}", self.name(*address)),
+            Opcode::RND(x, byte) => format!("{} := random 0x{:02x}", v(x), byte),
+            Opcode::DRW(x, y, n) => format!("sprite {} {} {}", v(x), v(y), n),
+            Opcode::LDVDT(x) => format!("{} := delay", v(x)),
+            Opcode::LDK(x) => format!("{} := key", v(x)),
+            Opcode::LDDTV(x) => format!("delay := {}", v(x)),
+            Opcode::LDST(x) => format!("buzzer := {}", v(x)),
+            Opcode::PITCH(x) => format!("pitch := {}", v(x)),
+            Opcode::ADDRI(x) => format!("i += {}", v(x)),
+            Opcode::LDF(x) => format!("i := hex {}", v(x)),
+            Opcode::LDHF(x) => format!("i := bighex {}", v(x)),
+            Opcode::LDB(x) => format!("bcd {}", v(x)),
+            Opcode::LDIM(x) => format!("save {}", v(x)),
+            Opcode::LDMI(x) => format!("load {}", v(x)),
+            Opcode::LDRV(x) => format!("saveflags {}", v(x)),
+            Opcode::LDVR(x) => format!("loadflags {}", v(x)),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+pub const PATTERN_LENGTH: usize = 16;
+
+/// XO-CHIP audio: a 128 bit pattern played back one bit per sample while the
+/// sound timer is running.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Audio {
+    pub pattern: [u8; PATTERN_LENGTH],
+    pub pitch: u8,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Audio {
+    pub fn new() -> Self {
+        Self {
+            // Square wave, so ROMs that never load a pattern still beep
+            pattern: [
+                0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00,
+                0x00, 0x00,
+            ],
+            pitch: 64,
+        }
+    }
+
+    /// Samples per second, 4000Hz at the default pitch of 64. `powf` needs std.
+    #[cfg(feature = "std")]
+    pub fn playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+}
@@ -1,180 +1,406 @@
+use super::audio::{Audio, PATTERN_LENGTH};
+use super::error::EmulatorError;
 use super::key::Key;
 use super::memory::Memory;
+use super::mode::Mode;
 use super::opcode::Opcode;
+use super::quirks::Quirks;
 use super::register::{Register, Registers};
-use super::vm::{HEIGHT, PROGRAM_OFFSET, WIDTH};
+use super::rng::{random_seed, RandomSource, Xorshift};
+use super::screen::{Screen, PLANES};
+use super::state::{StateReader, StateWriter};
+use super::vm::{BIG_FONT_OFFSET, PROGRAM_OFFSET};
 
-use rand::prelude::ThreadRng;
-use rand::{thread_rng, Rng};
-use std::fmt::{Display, Formatter};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+/// Nested CALLs allowed before a stack overflow
+pub const STACK_SIZE: usize = 16;
 
 pub struct CPU {
     registers: Registers,
     program_counter: u16,
     stack: Vec<u16>,
-    rng: ThreadRng,
+    seed: u64,
+    rng: Box<dyn RandomSource>,
+    mode: Mode,
+    quirks: Quirks,
+    flags: [u8; 16],
+    audio: Audio,
+    halted: bool,
 }
 
 impl CPU {
-    pub fn new() -> CPU {
+    pub fn new(mode: Mode, quirks: Quirks) -> CPU {
+        let seed = random_seed();
         CPU {
             registers: Registers::new(),
             program_counter: PROGRAM_OFFSET,
             stack: vec![],
-            rng: thread_rng(),
+            seed,
+            rng: Box::new(Xorshift::new(seed)),
+            mode,
+            quirks,
+            flags: [0; 16],
+            audio: Audio::new(),
+            halted: false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_u8(self.mode.id());
+        writer.put_u8(self.quirks.to_bits());
+        self.registers.save_state(writer);
+        writer.put_u16(self.program_counter);
+        writer.put_u8(self.stack.len() as u8);
+        self.stack
+            .iter()
+            .for_each(|address| writer.put_u16(*address));
+        writer.put_u64(self.seed);
+        writer.put_u64(self.rng.state());
+        self.flags.iter().for_each(|flag| writer.put_u8(*flag));
+        self.audio
+            .pattern
+            .iter()
+            .for_each(|byte| writer.put_u8(*byte));
+        writer.put_u8(self.audio.pitch);
+        writer.put_bool(self.halted)
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Self, EmulatorError> {
+        let mode = Mode::from_id(reader.get_u8()?)
+            .ok_or(EmulatorError::InvalidSaveState("unknown mode"))?;
+        let mut cpu = CPU::new(mode, Quirks::from_bits(reader.get_u8()?));
+        cpu.registers = Registers::load_state(reader)?;
+        cpu.program_counter = reader.get_u16()?;
+        let depth = reader.get_u8()? as usize;
+        if depth > STACK_SIZE {
+            return Err(EmulatorError::InvalidSaveState("stack too deep"));
+        }
+        for _ in 0..depth {
+            cpu.stack.push(reader.get_u16()?);
+        }
+        cpu.seed = reader.get_u64()?;
+        cpu.rng.restore(reader.get_u64()?);
+        for flag in cpu.flags.iter_mut() {
+            *flag = reader.get_u8()?;
+        }
+        for byte in cpu.audio.pattern.iter_mut() {
+            *byte = reader.get_u8()?;
+        }
+        cpu.audio.pitch = reader.get_u8()?;
+        cpu.halted = reader.get_bool()?;
+        Ok(cpu)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng.restore(seed)
+    }
+
+    pub fn set_random_source(&mut self, source: Box<dyn RandomSource>) {
+        self.rng = source
+    }
+
+    /// Moves a plugged in random source over from the CPU this one replaces,
+    /// keeping the state this CPU was restored with
+    pub fn adopt_random_source(&mut self, previous: &mut CPU) {
+        let state = self.rng.state();
+        core::mem::swap(&mut self.rng, &mut previous.rng);
+        self.rng.restore(state)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, address: u16) {
+        self.program_counter = address
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /// Return addresses, innermost call last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn audio(&self) -> &Audio {
+        &self.audio
+    }
+
+    pub fn is_sound_playing(&self) -> bool {
+        self.registers.st > 0
+    }
+
+    pub fn tick(
+        &mut self,
+        memory: &mut Memory,
+        screen: &mut Screen,
+        keys: &[Key],
+    ) -> Result<(), EmulatorError> {
+        if self.halted {
+            return Ok(());
+        }
+        let (opcode, _) = self.instruction_at(memory, self.program_counter)?;
+        self.execute(opcode, memory, screen, keys)
+    }
+
+    /// Decodes the instruction at `address` without running it, along with
+    /// its length in bytes
+    pub fn instruction_at(
+        &self,
+        memory: &Memory,
+        address: u16,
+    ) -> Result<(Opcode, u16), EmulatorError> {
+        let encoded_instruction = CPU::fetch(memory, address)?;
+        // F000 nnnn is the only instruction followed by an operand
+        if self.mode == Mode::XoChip && encoded_instruction == 0xf000 {
+            let operand = CPU::fetch(memory, address.wrapping_add(2))?;
+            let opcode = CPU::decode(self.mode, address, encoded_instruction, operand)?;
+            return Ok((opcode, 4));
         }
+        Ok((CPU::decode(self.mode, address, encoded_instruction, 0)?, 2))
     }
 
-    pub fn tick(&mut self, memory: &mut Memory, frame_buffer: &mut Vec<u32>, keys: &[Key]) {
-        let encoded_instruction = self.fetch(memory);
-        let opcode = CPU::decode(encoded_instruction);
-        self.execute(opcode, memory, frame_buffer, keys);
+    pub fn tick_timers(&mut self) {
         self.registers.tick()
     }
 
-    fn fetch(&self, memory: &Memory) -> u16 {
-        (memory.read(self.program_counter) as u16) << 8
-            | memory.read(self.program_counter + 1) as u16
+    fn fetch(memory: &Memory, address: u16) -> Result<u16, EmulatorError> {
+        Ok((memory.peek(address)? as u16) << 8 | memory.peek(address.wrapping_add(1))? as u16)
     }
 
-    fn decode(instruction: u16) -> Opcode {
-        match instruction >> 12 {
+    pub fn decode(
+        mode: Mode,
+        address: u16,
+        instruction: u16,
+        operand: u16,
+    ) -> Result<Opcode, EmulatorError> {
+        let unknown = EmulatorError::UnknownOpcode {
+            address,
+            instruction,
+        };
+        let super_chip = mode != Mode::Chip8;
+        let xo_chip = mode == Mode::XoChip;
+        let opcode = match instruction >> 12 {
             0x0 => match instruction {
                 0x00e0 => Opcode::CLS,
                 0x00ee => Opcode::RET,
+                0x00c0..=0x00cf if super_chip => Opcode::SCD((instruction & 0xf) as u8),
+                0x00d0..=0x00df if xo_chip => Opcode::SCU((instruction & 0xf) as u8),
+                0x00fb if super_chip => Opcode::SCR,
+                0x00fc if super_chip => Opcode::SCL,
+                0x00fd if super_chip => Opcode::EXIT,
+                0x00fe if super_chip => Opcode::LOW,
+                0x00ff if super_chip => Opcode::HIGH,
                 _ => Opcode::SYS(instruction & 0xfff),
             },
             0x1 => Opcode::JP(instruction & 0xfff),
             0x2 => Opcode::CALL(instruction & 0xfff),
             0x3 => Opcode::SEI(
-                Register::new((instruction >> 8) & 0xf),
+                Register::new((instruction >> 8) & 0xf)?,
                 (instruction & 0x0ff) as u8,
             ),
             0x4 => Opcode::SNEI(
-                Register::new((instruction >> 8) & 0xf),
+                Register::new((instruction >> 8) & 0xf)?,
                 (instruction & 0x0ff) as u8,
             ),
-            0x5 => Opcode::SE(
-                Register::new((instruction >> 8) & 0xf),
-                Register::new((instruction >> 4) & 0xf),
-            ),
+            0x5 => match instruction & 0xf {
+                0x0 => Opcode::SE(
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
+                ),
+                0x2 if xo_chip => Opcode::SAVE(
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
+                ),
+                0x3 if xo_chip => Opcode::LOAD(
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
+                ),
+                _ => return Err(unknown),
+            },
             0x6 => Opcode::LDI(
-                Register::new((instruction >> 8) & 0xf),
+                Register::new((instruction >> 8) & 0xf)?,
                 (instruction & 0x0ff) as u8,
             ),
             0x7 => Opcode::ADDI(
-                Register::new((instruction >> 8) & 0xf),
+                Register::new((instruction >> 8) & 0xf)?,
                 (instruction & 0x0ff) as u8,
             ),
             0x8 => match instruction & 0xf {
                 0x0 => Opcode::LD(
-                    Register::new((instruction >> 8) & 0xf),
-                    Register::new((instruction >> 4) & 0xf),
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
                 ),
                 0x1 => Opcode::OR(
-                    Register::new((instruction >> 8) & 0xf),
-                    Register::new((instruction >> 4) & 0xf),
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
                 ),
                 0x2 => Opcode::AND(
-                    Register::new((instruction >> 8) & 0xf),
-                    Register::new((instruction >> 4) & 0xf),
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
                 ),
                 0x3 => Opcode::XOR(
-                    Register::new((instruction >> 8) & 0xf),
-                    Register::new((instruction >> 4) & 0xf),
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
                 ),
                 0x4 => Opcode::ADD(
-                    Register::new((instruction >> 8) & 0xf),
-                    Register::new((instruction >> 4) & 0xf),
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
                 ),
                 0x5 => Opcode::SUBR(
-                    Register::new((instruction >> 8) & 0xf),
-                    Register::new((instruction >> 4) & 0xf),
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
+                ),
+                0x6 => Opcode::SHR(
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
                 ),
-                0x6 => Opcode::SHR(Register::new((instruction >> 8) & 0xf)),
                 0x7 => Opcode::SUBN(
-                    Register::new((instruction >> 8) & 0xf),
-                    Register::new((instruction >> 4) & 0xf),
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
+                ),
+                0xe => Opcode::SHL(
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
                 ),
-                0xe => Opcode::SHL(Register::new((instruction >> 8) & 0xf)),
-                _ => panic!("{:#06x}", instruction),
+                _ => return Err(unknown),
             },
             0x9 => {
                 if instruction & 0xf != 0x0 {
-                    panic!("Instruction {} (SNE Vx Vy) should end with 0", instruction);
+                    return Err(unknown);
                 }
                 Opcode::SNE(
-                    Register::new((instruction >> 8) & 0xf),
-                    Register::new((instruction >> 4) & 0xf),
+                    Register::new((instruction >> 8) & 0xf)?,
+                    Register::new((instruction >> 4) & 0xf)?,
                 )
             }
             0xa => Opcode::LDII(instruction & 0x0fff),
             0xb => Opcode::JPA(instruction & 0x0fff),
             0xc => Opcode::RND(
-                Register::new((instruction >> 8) & 0xf),
+                Register::new((instruction >> 8) & 0xf)?,
                 (instruction & 0x0ff) as u8,
             ),
             0xd => Opcode::DRW(
-                Register::new((instruction >> 8) & 0xf),
-                Register::new((instruction >> 4) & 0xf),
+                Register::new((instruction >> 8) & 0xf)?,
+                Register::new((instruction >> 4) & 0xf)?,
                 (instruction & 0x000f) as u8, // n
             ),
             0xe => match instruction & 0xff {
-                0x9e => Opcode::SKP(Register::new((instruction >> 8) & 0xf)),
-                0xa1 => Opcode::SKNP(Register::new((instruction >> 8) & 0xf)),
-                _ => panic!("{:#06x}", instruction),
+                0x9e => Opcode::SKP(Register::new((instruction >> 8) & 0xf)?),
+                0xa1 => Opcode::SKNP(Register::new((instruction >> 8) & 0xf)?),
+                _ => return Err(unknown),
             },
             0xf => match instruction & 0xff {
-                0x07 => Opcode::LDVDT(Register::new((instruction >> 8) & 0xf)),
-                0x0a => Opcode::LDK(Register::new((instruction >> 8) & 0xf)),
-                0x15 => Opcode::LDDTV(Register::new((instruction >> 8) & 0xf)),
-                0x18 => Opcode::LDST(Register::new((instruction >> 8) & 0xf)),
-                0x1e => Opcode::ADDRI(Register::new((instruction >> 8) & 0xf)),
-                0x29 => Opcode::LDF(Register::new((instruction >> 8) & 0xf)),
-                0x33 => Opcode::LDB(Register::new((instruction >> 8) & 0xf)),
-                0x55 => Opcode::LDIM(Register::new((instruction >> 8) & 0xf)),
-                0x65 => Opcode::LDMI(Register::new((instruction >> 8) & 0xf)),
-                _ => panic!("{:#06x}", instruction),
+                0x00 if xo_chip && instruction == 0xf000 => Opcode::LDIL(operand),
+                0x01 if xo_chip => Opcode::PLANE(((instruction >> 8) & 0xf) as u8),
+                0x02 if xo_chip && instruction == 0xf002 => Opcode::AUDIO,
+                0x07 => Opcode::LDVDT(Register::new((instruction >> 8) & 0xf)?),
+                0x0a => Opcode::LDK(Register::new((instruction >> 8) & 0xf)?),
+                0x15 => Opcode::LDDTV(Register::new((instruction >> 8) & 0xf)?),
+                0x18 => Opcode::LDST(Register::new((instruction >> 8) & 0xf)?),
+                0x1e => Opcode::ADDRI(Register::new((instruction >> 8) & 0xf)?),
+                0x29 => Opcode::LDF(Register::new((instruction >> 8) & 0xf)?),
+                0x30 if super_chip => Opcode::LDHF(Register::new((instruction >> 8) & 0xf)?),
+                0x33 => Opcode::LDB(Register::new((instruction >> 8) & 0xf)?),
+                0x3a if xo_chip => Opcode::PITCH(Register::new((instruction >> 8) & 0xf)?),
+                0x55 => Opcode::LDIM(Register::new((instruction >> 8) & 0xf)?),
+                0x65 => Opcode::LDMI(Register::new((instruction >> 8) & 0xf)?),
+                0x75 if super_chip => Opcode::LDRV(Register::new((instruction >> 8) & 0xf)?),
+                0x85 if super_chip => Opcode::LDVR(Register::new((instruction >> 8) & 0xf)?),
+                _ => return Err(unknown),
             },
-            _ => panic!("{:#06x}", instruction),
-        }
+            _ => return Err(unknown),
+        };
+        Ok(opcode)
     }
 
     fn execute(
         &mut self,
         opcode: Opcode,
         memory: &mut Memory,
-        frame_buffer: &mut Vec<u32>,
+        screen: &mut Screen,
         keys: &[Key],
-    ) {
+    ) -> Result<(), EmulatorError> {
         match opcode {
             Opcode::SYS(_) => {} // unimplemented in modern interpreters?
-            Opcode::CLS => frame_buffer.iter_mut().for_each(|x| *x = 0),
+            Opcode::CLS => screen.clear(),
             Opcode::RET => {
-                self.program_counter = self.stack.pop().unwrap();
+                self.program_counter = self.stack.pop().ok_or(EmulatorError::StackUnderflow {
+                    address: self.program_counter,
+                })?;
+            }
+            Opcode::SCD(n) => screen.scroll_down(n as usize),
+            Opcode::SCU(n) => screen.scroll_up(n as usize),
+            Opcode::SCR => screen.scroll_right(4),
+            Opcode::SCL => screen.scroll_left(4),
+            Opcode::EXIT => {
+                self.halted = true;
+                return Ok(());
             }
+            Opcode::LOW => screen.set_hires(false),
+            Opcode::HIGH => screen.set_hires(true),
             Opcode::JP(addr) => {
                 self.program_counter = addr;
-                return;
+                return Ok(());
             }
             Opcode::CALL(addr) => {
+                if self.stack.len() == STACK_SIZE {
+                    return Err(EmulatorError::StackOverflow {
+                        address: self.program_counter,
+                    });
+                }
                 self.stack.push(self.program_counter);
                 self.program_counter = addr;
-                return;
+                return Ok(());
             }
             Opcode::SEI(register, byte) => {
                 if self.registers.read(&register) == byte {
-                    self.program_counter += 2;
+                    self.skip(memory)?;
                 }
             }
             Opcode::SNEI(register, byte) => {
                 if self.registers.read(&register) != byte {
-                    self.program_counter += 2;
+                    self.skip(memory)?;
                 }
             }
             Opcode::SE(register_1, register_2) => {
                 if self.registers.read(&register_1) == self.registers.read(&register_2) {
-                    self.program_counter += 2;
+                    self.skip(memory)?;
+                }
+            }
+            Opcode::SAVE(register_1, register_2) => {
+                for (offset, id) in CPU::register_range(&register_1, &register_2).enumerate() {
+                    let value = self.registers.read(&Register::new(id)?);
+                    memory.write(self.registers.i.wrapping_add(offset as u16), value)?;
+                }
+            }
+            Opcode::LOAD(register_1, register_2) => {
+                for (offset, id) in CPU::register_range(&register_1, &register_2).enumerate() {
+                    let value = memory.read(self.registers.i.wrapping_add(offset as u16))?;
+                    self.registers.write(&Register::new(id)?, value);
                 }
             }
             Opcode::LDI(register, value) => self.registers.write(&register, value),
@@ -189,17 +415,20 @@ impl CPU {
             Opcode::OR(register_1, register_2) => {
                 let value_1 = self.registers.read(&register_1);
                 let value_2 = self.registers.read(&register_2);
-                self.registers.write(&register_1, value_1 | value_2)
+                self.registers.write(&register_1, value_1 | value_2);
+                self.reset_vf_after_logic()
             }
             Opcode::AND(register_1, register_2) => {
                 let value_1 = self.registers.read(&register_1);
                 let value_2 = self.registers.read(&register_2);
-                self.registers.write(&register_1, value_1 & value_2)
+                self.registers.write(&register_1, value_1 & value_2);
+                self.reset_vf_after_logic()
             }
             Opcode::XOR(register_1, register_2) => {
                 let value_1 = self.registers.read(&register_1);
                 let value_2 = self.registers.read(&register_2);
-                self.registers.write(&register_1, value_1 ^ value_2)
+                self.registers.write(&register_1, value_1 ^ value_2);
+                self.reset_vf_after_logic()
             }
             Opcode::ADD(register_1, register_2) => {
                 let value_1 = self.registers.read(&register_1) as u16;
@@ -217,10 +446,14 @@ impl CPU {
                     .write(&Register::vf(), if overflow { 0 } else { 1 });
                 self.registers.write(&register_1, value)
             }
-            Opcode::SHR(register) => {
-                let value = self.registers.read(&register);
-                self.registers.write(&Register::vf(), value & 0x1);
-                self.registers.write(&register, value >> 1)
+            Opcode::SHR(register_1, register_2) => {
+                let value = if self.quirks.shift_uses_vy {
+                    self.registers.read(&register_2)
+                } else {
+                    self.registers.read(&register_1)
+                };
+                self.registers.write(&register_1, value >> 1);
+                self.registers.write(&Register::vf(), value & 0x1)
             }
             Opcode::SUBN(register_1, register_2) => {
                 let value_1 = self.registers.read(&register_1);
@@ -230,63 +463,106 @@ impl CPU {
                     .write(&Register::vf(), if overflow { 0 } else { 1 });
                 self.registers.write(&register_1, value)
             }
-            Opcode::SHL(register) => {
-                let value = self.registers.read(&register);
-                self.registers.write(&Register::vf(), (value >> 7) & 0x1);
-                self.registers.write(&register, value << 1)
+            Opcode::SHL(register_1, register_2) => {
+                let value = if self.quirks.shift_uses_vy {
+                    self.registers.read(&register_2)
+                } else {
+                    self.registers.read(&register_1)
+                };
+                self.registers.write(&register_1, value << 1);
+                self.registers.write(&Register::vf(), (value >> 7) & 0x1)
             }
             Opcode::SNE(register_1, register_2) => {
                 if self.registers.read(&register_1) != self.registers.read(&register_2) {
-                    self.program_counter += 2;
+                    self.skip(memory)?;
                 }
             }
             Opcode::LDII(nnn) => self.registers.i = nnn,
+            Opcode::LDIL(nnnn) => {
+                self.registers.i = nnnn;
+                // Step over the operand
+                self.advance(2)?;
+            }
+            Opcode::PLANE(n) => screen.select_planes(n),
+            Opcode::AUDIO => {
+                for offset in 0..PATTERN_LENGTH {
+                    self.audio.pattern[offset] =
+                        memory.read(self.registers.i.wrapping_add(offset as u16))?;
+                }
+            }
+            Opcode::PITCH(register) => self.audio.pitch = self.registers.read(&register),
             Opcode::JPA(addr) => {
-                let value = self.registers.read(&Register::new(0));
+                let register = if self.quirks.jump_uses_vx {
+                    Register::new((addr >> 8) & 0xf)?
+                } else {
+                    Register::new(0)?
+                };
+                let value = self.registers.read(&register);
                 self.program_counter = addr.wrapping_add(value as u16);
-                return;
+                return Ok(());
             }
             Opcode::RND(register, byte) => {
-                let value: u8 = self.rng.gen();
+                let value = self.rng.next_u8();
                 self.registers.write(&register, value & byte);
             }
             Opcode::DRW(x_register, y_register, n) => {
-                let x_offset = self.registers.read(&x_register);
-                let y_offset = self.registers.read(&y_register);
-                let mut changed = 0;
-                for ys in 0..n {
-                    let line = memory.read(self.registers.i + ys as u16);
-                    for xs in 0..8 {
-                        if (line & (0x80 >> xs)) != 0 {
-                            let x = (x_offset as usize + xs as usize) % WIDTH;
-                            let y = (y_offset as usize + ys as usize) % HEIGHT;
-
-                            let l = y * WIDTH + x;
-                            if frame_buffer[l] != 0 {
-                                changed = 1
-                            }
-                            frame_buffer[l] = !frame_buffer[l];
+                let x = self.registers.read(&x_register) as usize;
+                let y = self.registers.read(&y_register) as usize;
+                let big = n == 0 && self.mode != Mode::Chip8;
+                // SUPER-CHIP 1.1 only drew 16x16 sprites in hires
+                let wide = big && (screen.is_hires() || !self.quirks.lores_sprites_8x16);
+                let height = if big { 16 } else { n };
+                let mut address = self.registers.i;
+                let mut collisions = 0;
+                let mut clipped = 0;
+                // Selected planes are drawn in order, each from its own copy of the sprite data
+                for plane in (0..PLANES).map(|p| 1 << p) {
+                    if screen.planes() & plane == 0 {
+                        continue;
+                    }
+                    let mut rows = vec![];
+                    if wide {
+                        // 16x16 sprite, two bytes per row
+                        for _ in 0..height {
+                            let left = memory.read(address)? as u16;
+                            let right = memory.read(address.wrapping_add(1))? as u16;
+                            rows.push(left << 8 | right);
+                            address = address.wrapping_add(2);
+                        }
+                    } else {
+                        for _ in 0..height {
+                            rows.push((memory.read(address)? as u16) << 8);
+                            address = address.wrapping_add(1);
                         }
                     }
+                    let (plane_collisions, plane_clipped) =
+                        screen.draw(x, y, &rows, plane, self.quirks.clip_sprites);
+                    collisions += plane_collisions;
+                    clipped = plane_clipped;
                 }
-                self.registers.write(&Register::vf(), changed);
+                let vf = if self.quirks.vf_counts_rows && screen.is_hires() {
+                    collisions + clipped
+                } else {
+                    (collisions > 0) as usize
+                };
+                self.registers.write(&Register::vf(), vf as u8);
             }
             Opcode::SKP(register) => {
                 let value = self.registers.read(&register);
                 if keys.iter().any(|x| (*x as u8) == value) {
-                    self.program_counter += 2;
+                    self.skip(memory)?;
                 }
             }
             Opcode::SKNP(register) => {
                 let value = self.registers.read(&register);
                 if keys.iter().all(|x| (*x as u8) != value) {
-                    self.program_counter += 2;
+                    self.skip(memory)?;
                 }
             }
             Opcode::LDVDT(register) => self.registers.write(&register, self.registers.dt),
             Opcode::LDK(register) => {
                 if keys.is_empty() {
-                    return;
+                    return Ok(());
                 }
                 self.registers
                     .write(&register, *keys.first().unwrap() as u8)
@@ -299,46 +575,235 @@ impl CPU {
                     .i
                     .wrapping_add(self.registers.read(&register) as u16)
             }
-            Opcode::LDF(register) => self.registers.i = (self.registers.read(&register) * 5) as u16,
+            Opcode::LDF(register) => self.registers.i = self.registers.read(&register) as u16 * 5,
+            Opcode::LDHF(register) => {
+                self.registers.i = BIG_FONT_OFFSET + self.registers.read(&register) as u16 * 10
+            }
             Opcode::LDB(register) => {
                 let value = self.registers.read(&register);
                 let first = value / 100;
                 let second = (value % 100) / 10;
                 let third = value % 10;
-                memory.write(self.registers.i, first);
-                memory.write(self.registers.i + 1, second);
-                memory.write(self.registers.i + 2, third)
+                memory.write(self.registers.i, first)?;
+                memory.write(self.registers.i.wrapping_add(1), second)?;
+                memory.write(self.registers.i.wrapping_add(2), third)?
             }
             Opcode::LDIM(register) => {
                 let id = register.id;
                 for i in 0..=id {
-                    let register = Register::new(i as u16);
+                    let register = Register::new(i as u16)?;
                     let value = self.registers.read(&register);
-                    memory.write(self.registers.i + i as u16, value);
+                    memory.write(self.registers.i.wrapping_add(i as u16), value)?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.registers.i = self.registers.i.wrapping_add(id as u16 + 1);
                 }
             }
             Opcode::LDMI(register) => {
                 let id = register.id;
                 for i in 0..=id {
-                    let register = Register::new(i as u16);
-                    let value = memory.read(self.registers.i + i as u16);
+                    let register = Register::new(i as u16)?;
+                    let value = memory.read(self.registers.i.wrapping_add(i as u16))?;
                     self.registers.write(&register, value);
                 }
+                if self.quirks.load_store_increments_i {
+                    self.registers.i = self.registers.i.wrapping_add(id as u16 + 1);
+                }
             }
+            Opcode::LDRV(register) => {
+                let id = register.id as usize;
+                for i in 0..=id {
+                    self.flags[i] = self.registers.read(&Register::new(i as u16)?);
+                }
+            }
+            Opcode::LDVR(register) => {
+                let id = register.id as usize;
+                for i in 0..=id {
+                    self.registers
+                        .write(&Register::new(i as u16)?, self.flags[i]);
+                }
+            }
+        }
+        self.advance(2)
+    }
+
+    // Skips the next instruction, which in XO-CHIP may be the 4 byte F000 nnnn
+    fn skip(&mut self, memory: &Memory) -> Result<(), EmulatorError> {
+        let next = self.program_counter.wrapping_add(2);
+        if self.mode == Mode::XoChip && CPU::fetch(memory, next)? == 0xf000 {
+            self.advance(4)
+        } else {
+            self.advance(2)
+        }
+    }
+
+    // The program counter doesn't wrap, running past the end of memory is an error
+    fn advance(&mut self, bytes: u16) -> Result<(), EmulatorError> {
+        self.program_counter =
+            self.program_counter
+                .checked_add(bytes)
+                .ok_or(EmulatorError::OutOfBoundsRead {
+                    address: self.program_counter,
+                })?;
+        Ok(())
+    }
+
+    // Registers from x to y, in descending order when y is lower than x
+    fn register_range(
+        register_1: &Register,
+        register_2: &Register,
+    ) -> Box<dyn Iterator<Item = u16>> {
+        let (x, y) = (register_1.id as u16, register_2.id as u16);
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.registers.write(&Register::vf(), 0)
         }
-        self.program_counter += 2;
     }
 }
 
 impl Display for CPU {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.registers)?;
         writeln!(f)?;
-        writeln!(f, "PC: {}", self.program_counter)?;
+        writeln!(f, "PC: {:04x}", self.program_counter)?;
         writeln!(f, "Stack:")?;
         for v in self.stack.iter() {
-            write!(f, "{}\t", v)?;
+            write!(f, "{:04x}\t", v)?;
         }
         writeln!(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::vm::VM;
+
+    fn run(program: &[u8], sprite: &[u8], mode: Mode, quirks: Quirks, steps: usize) -> VM {
+        // The sprite follows the program, which loads I with its address first
+        let address = PROGRAM_OFFSET as usize + 2 + program.len();
+        let mut rom = vec![0xa0 | (address >> 8) as u8, address as u8];
+        rom.extend_from_slice(program);
+        rom.extend_from_slice(sprite);
+        let mut vm = VM::new(&rom, mode, quirks).unwrap();
+        for _ in 0..=steps {
+            vm.step_instruction(&[]).unwrap();
+        }
+        vm
+    }
+
+    fn vf(vm: &VM) -> u8 {
+        vm.cpu().registers().read(&Register::vf())
+    }
+
+    fn lit(vm: &VM, x: usize, y: usize) -> bool {
+        vm.pixels()[y * vm.resolution().0 + x] != 0
+    }
+
+    #[test]
+    fn schip_resolution() {
+        // HIGH; LOW
+        let program = [0x00, 0xff, 0x00, 0xfe];
+        let vm = run(&program, &[], Mode::SuperChip, Quirks::SUPER_CHIP, 1);
+        assert_eq!(vm.resolution(), (128, 64));
+        let vm = run(&program, &[], Mode::SuperChip, Quirks::SUPER_CHIP, 2);
+        assert_eq!(vm.resolution(), (64, 32));
+    }
+
+    #[test]
+    fn schip_scrolling() {
+        // HIGH; LD V0, 0; DRW V0, V0, 1; SCD 3; SCR; SCL
+        let program = [
+            0x00, 0xff, 0x60, 0x00, 0xd0, 0x01, 0x00, 0xc3, 0x00, 0xfb, 0x00, 0xfc,
+        ];
+        let sprite = [0x80];
+        let vm = run(&program, &sprite, Mode::SuperChip, Quirks::SUPER_CHIP, 4);
+        assert!(lit(&vm, 0, 3));
+        assert!(!lit(&vm, 0, 0));
+        let vm = run(&program, &sprite, Mode::SuperChip, Quirks::SUPER_CHIP, 5);
+        assert!(lit(&vm, 4, 3));
+        assert!(!lit(&vm, 0, 3));
+        let vm = run(&program, &sprite, Mode::SuperChip, Quirks::SUPER_CHIP, 6);
+        assert!(lit(&vm, 0, 3));
+    }
+
+    #[test]
+    fn schip_big_font_and_flags() {
+        // LD V0, 2; LD HF, V0; LD V1, 7; LD R, V1; LD V0, 0; LD V1, 0; LD V1, R
+        let program = [
+            0x60, 0x02, 0xf0, 0x30, 0x61, 0x07, 0xf1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xf1, 0x85,
+        ];
+        let vm = run(&program, &[], Mode::SuperChip, Quirks::SUPER_CHIP, 2);
+        assert_eq!(vm.cpu().registers().i, BIG_FONT_OFFSET + 20);
+        let vm = run(&program, &[], Mode::SuperChip, Quirks::SUPER_CHIP, 7);
+        assert_eq!(vm.cpu().registers().read(&Register::new(0).unwrap()), 2);
+        assert_eq!(vm.cpu().registers().read(&Register::new(1).unwrap()), 7);
+    }
+
+    #[test]
+    fn schip_exit() {
+        let vm = run(&[0x00, 0xfd], &[], Mode::SuperChip, Quirks::SUPER_CHIP, 1);
+        assert!(vm.is_halted());
+    }
+
+    #[test]
+    fn schip_opcodes_need_schip_mode() {
+        let mut vm = VM::new(&[0x00, 0xff], Mode::Chip8, Quirks::COSMAC_VIP).unwrap();
+        vm.step_instruction(&[]).unwrap();
+        assert_eq!(vm.resolution(), (64, 32));
+    }
+
+    #[test]
+    fn big_sprites_are_8x16_in_schip_lores() {
+        // LD V0, 0; DRW V0, V0, 0
+        let program = [0x60, 0x00, 0xd0, 0x00];
+        let sprite = [0xff; 32];
+
+        let vm = run(&program, &sprite, Mode::SuperChip, Quirks::SUPER_CHIP, 2);
+        assert!(lit(&vm, 7, 15));
+        assert!(!lit(&vm, 8, 0));
+        assert!(!lit(&vm, 0, 16));
+
+        let vm = run(&program, &sprite, Mode::XoChip, Quirks::XO_CHIP, 2);
+        assert!(lit(&vm, 15, 15));
+        assert!(!lit(&vm, 0, 16));
+    }
+
+    #[test]
+    fn vf_counts_rows_in_schip_hires() {
+        // HIGH; LD V0, 0; LD V1, 56; DRW V0, V1, 0 twice, the last 8 rows clipped
+        let program = [0x00, 0xff, 0x60, 0x00, 0x61, 0x38, 0xd0, 0x10, 0xd0, 0x10];
+        let sprite = [0xff; 32];
+
+        let vm = run(&program, &sprite, Mode::SuperChip, Quirks::SUPER_CHIP, 4);
+        assert_eq!(vf(&vm), 8);
+        let vm = run(&program, &sprite, Mode::SuperChip, Quirks::SUPER_CHIP, 5);
+        assert_eq!(vf(&vm), 16);
+
+        let vm = run(&program, &sprite, Mode::SuperChip, Quirks::CHIP_48, 4);
+        assert_eq!(vf(&vm), 0);
+        let vm = run(&program, &sprite, Mode::SuperChip, Quirks::CHIP_48, 5);
+        assert_eq!(vf(&vm), 1);
+    }
+
+    #[test]
+    fn vf_is_a_flag_in_schip_lores() {
+        // LD V0, 0; DRW V0, V0, 0 twice
+        let program = [0x60, 0x00, 0xd0, 0x00, 0xd0, 0x00];
+        let vm = run(
+            &program,
+            &[0xff; 32],
+            Mode::SuperChip,
+            Quirks::SUPER_CHIP,
+            3,
+        );
+        assert_eq!(vf(&vm), 1);
+    }
+}
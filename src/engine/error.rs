@@ -0,0 +1,53 @@
+use core::fmt::{Display, Formatter};
+
+/// Why a ROM, an instruction or a save state was rejected
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EmulatorError {
+    UnknownOpcode { address: u16, instruction: u16 },
+    InvalidRegister(u16),
+    StackUnderflow { address: u16 },
+    StackOverflow { address: u16 },
+    ProtectedWrite { address: u16 },
+    OutOfBoundsRead { address: u16 },
+    OutOfBoundsWrite { address: u16 },
+    RomTooLarge { size: usize },
+    InvalidSaveState(&'static str),
+    UnsupportedSaveStateVersion(u8),
+}
+
+impl Display for EmulatorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            EmulatorError::UnknownOpcode {
+                address,
+                instruction,
+            } => write!(f, "unknown opcode {:#06x} at {:#05x}", instruction, address),
+            EmulatorError::InvalidRegister(id) => write!(f, "invalid register id {}", id),
+            EmulatorError::StackUnderflow { address } => {
+                write!(f, "RET with an empty stack at {:#05x}", address)
+            }
+            EmulatorError::StackOverflow { address } => {
+                write!(f, "CALL with a full stack at {:#05x}", address)
+            }
+            EmulatorError::ProtectedWrite { address } => {
+                write!(f, "write to read only memory at {:#05x}", address)
+            }
+            EmulatorError::OutOfBoundsRead { address } => {
+                write!(f, "read outside of memory at {:#06x}", address)
+            }
+            EmulatorError::OutOfBoundsWrite { address } => {
+                write!(f, "write outside of memory at {:#06x}", address)
+            }
+            EmulatorError::RomTooLarge { size } => {
+                write!(f, "ROM of {} bytes does not fit in memory", size)
+            }
+            EmulatorError::InvalidSaveState(reason) => write!(f, "invalid save state: {}", reason),
+            EmulatorError::UnsupportedSaveStateVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EmulatorError {}
@@ -1,3 +1,4 @@
+/// One of the 16 keys of the hexadecimal keypad
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Key {
     Key0 = 0x0,
@@ -19,29 +20,27 @@ pub enum Key {
 }
 
 impl Key {
-    pub fn from(key: minifb::Key) -> Option<Self> {
-        match key {
-            minifb::Key::Key1 => Some(Self::Key1),
-            minifb::Key::Key2 => Some(Self::Key2),
-            minifb::Key::Key3 => Some(Self::Key3),
-            minifb::Key::Key4 => Some(Self::KeyC),
+    pub const ALL: [Key; 16] = [
+        Self::Key0,
+        Self::Key1,
+        Self::Key2,
+        Self::Key3,
+        Self::Key4,
+        Self::Key5,
+        Self::Key6,
+        Self::Key7,
+        Self::Key8,
+        Self::Key9,
+        Self::KeyA,
+        Self::KeyB,
+        Self::KeyC,
+        Self::KeyD,
+        Self::KeyE,
+        Self::KeyF,
+    ];
 
-            minifb::Key::Q => Some(Self::Key4),
-            minifb::Key::W => Some(Self::Key5),
-            minifb::Key::E => Some(Self::Key6),
-            minifb::Key::R => Some(Self::KeyD),
-
-            minifb::Key::A => Some(Self::Key7),
-            minifb::Key::S => Some(Self::Key8),
-            minifb::Key::D => Some(Self::Key9),
-            minifb::Key::F => Some(Self::KeyE),
-
-            minifb::Key::Z => Some(Self::KeyA),
-            minifb::Key::X => Some(Self::Key0),
-            minifb::Key::C => Some(Self::KeyB),
-            minifb::Key::V => Some(Self::KeyF),
-
-            _ => None,
-        }
+    /// The key with a hexadecimal value, as used by SKP, SKNP and LD Vx, K
+    pub fn from_value(value: u8) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
     }
 }
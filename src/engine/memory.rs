@@ -1,32 +1,143 @@
+use super::error::EmulatorError;
+use super::state::{StateReader, StateWriter};
 use super::vm::PROGRAM_OFFSET;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
 
-const MEMORY_LENGTH: usize = 0xfff;
+pub const MEMORY_LENGTH: usize = 0x1000;
+pub const XO_MEMORY_LENGTH: usize = 0x10000;
+
+/// Range of addresses, both ends included, to watch for reads and/or writes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub reads: bool,
+    pub writes: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WatchHit {
+    pub address: u16,
+    pub write: bool,
+    pub value: u8,
+}
 
 pub struct Memory {
-    memory: [u8; MEMORY_LENGTH],
+    memory: Vec<u8>,
+    watchpoints: Vec<Watchpoint>,
+    // First watched access since the last `take_hit`
+    hit: Cell<Option<WatchHit>>,
 }
 
 impl Memory {
-    pub fn new() -> Self {
+    pub fn new(length: usize) -> Self {
         Self {
-            memory: [0; MEMORY_LENGTH],
+            memory: vec![0; length],
+            watchpoints: vec![],
+            hit: Cell::new(None),
+        }
+    }
+
+    pub fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        self.watchpoints = watchpoints
+    }
+
+    /// Keeps the watchpoints of the memory this one replaces, e.g. on loading a state
+    pub fn adopt_watchpoints(&mut self, previous: &mut Memory) {
+        core::mem::swap(&mut self.watchpoints, &mut previous.watchpoints)
+    }
+
+    pub fn take_hit(&self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+
+    fn watch(&self, address: u16, write: bool, value: u8) {
+        let watched = self.watchpoints.iter().any(|watchpoint| {
+            (watchpoint.start..=watchpoint.end).contains(&address)
+                && if write {
+                    watchpoint.writes
+                } else {
+                    watchpoint.reads
+                }
+        });
+        if watched && self.hit.get().is_none() {
+            self.hit.set(Some(WatchHit {
+                address,
+                write,
+                value,
+            }))
         }
     }
 
-    pub fn load(&mut self, data: &[u8], offset: u16) {
+    pub fn load(&mut self, data: &[u8], offset: u16) -> Result<(), EmulatorError> {
+        if offset as usize + data.len() > self.memory.len() {
+            return Err(EmulatorError::RomTooLarge { size: data.len() });
+        }
         for (address, value) in data.iter().enumerate() {
-            self.memory[offset as usize + address as usize] = *value
+            self.memory[offset as usize + address] = *value
         }
+        Ok(())
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_bytes(&self.memory)
     }
 
-    pub fn read(&self, address: u16) -> u8 {
-        self.memory[address as usize]
+    pub fn load_state(reader: &mut StateReader, length: usize) -> Result<Self, EmulatorError> {
+        let memory = reader.get_bytes()?;
+        if memory.len() != length {
+            return Err(EmulatorError::InvalidSaveState(
+                "memory size does not match mode",
+            ));
+        }
+        let mut loaded = Self::new(length);
+        loaded.memory.copy_from_slice(memory);
+        Ok(loaded)
+    }
+
+    /// A data read by the program, which triggers watchpoints
+    pub fn read(&self, address: u16) -> Result<u8, EmulatorError> {
+        let value = self.peek(address)?;
+        if !self.watchpoints.is_empty() {
+            self.watch(address, false, value)
+        }
+        Ok(value)
     }
 
-    pub fn write(&mut self, address: u16, value: u8) {
+    /// Reads without triggering watchpoints, for instruction fetches and debuggers
+    pub fn peek(&self, address: u16) -> Result<u8, EmulatorError> {
+        self.memory
+            .get(address as usize)
+            .copied()
+            .ok_or(EmulatorError::OutOfBoundsRead { address })
+    }
+
+    /// Writes anywhere, fonts included, without triggering watchpoints
+    pub fn poke(&mut self, address: u16, value: u8) -> Result<(), EmulatorError> {
+        match self.memory.get_mut(address as usize) {
+            Some(cell) => {
+                *cell = value;
+                Ok(())
+            }
+            None => Err(EmulatorError::OutOfBoundsWrite { address }),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) -> Result<(), EmulatorError> {
         if address < PROGRAM_OFFSET {
-            panic!("ROM writing to read only data!");
+            return Err(EmulatorError::ProtectedWrite { address });
+        }
+        match self.memory.get_mut(address as usize) {
+            Some(cell) => {
+                *cell = value;
+                if !self.watchpoints.is_empty() {
+                    self.watch(address, true, value)
+                }
+                Ok(())
+            }
+            None => Err(EmulatorError::OutOfBoundsWrite { address }),
         }
-        self.memory[address as usize] = value
     }
 }
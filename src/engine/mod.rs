@@ -1,6 +1,18 @@
-pub mod vm;
-mod cpu;
-mod opcode;
-mod register;
-pub mod memory;
+pub mod audio;
+pub mod cpu;
+pub mod error;
 pub mod key;
+pub mod memory;
+pub mod mode;
+pub mod opcode;
+pub mod palette;
+#[cfg(feature = "std")]
+pub mod profile;
+pub mod quirks;
+pub mod register;
+pub mod rng;
+mod screen;
+mod state;
+#[cfg(feature = "std")]
+pub mod trace;
+pub mod vm;
@@ -0,0 +1,56 @@
+use super::memory::{MEMORY_LENGTH, XO_MEMORY_LENGTH};
+use super::quirks::Quirks;
+
+/// Instruction set and memory size of the machine
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum Mode {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Mode {
+    /// Parses names like `chip8` and `xo-chip`
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "chip8" | "chip-8" => Some(Mode::Chip8),
+            "schip" | "super-chip" => Some(Mode::SuperChip),
+            "xochip" | "xo-chip" => Some(Mode::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Mode::Chip8 => 0,
+            Mode::SuperChip => 1,
+            Mode::XoChip => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Mode> {
+        match id {
+            0 => Some(Mode::Chip8),
+            1 => Some(Mode::SuperChip),
+            2 => Some(Mode::XoChip),
+            _ => None,
+        }
+    }
+
+    /// Quirks of the interpreter the mode is named after
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Mode::Chip8 => Quirks::COSMAC_VIP,
+            Mode::SuperChip => Quirks::SUPER_CHIP,
+            Mode::XoChip => Quirks::XO_CHIP,
+        }
+    }
+
+    pub fn memory_length(&self) -> usize {
+        match self {
+            Mode::Chip8 | Mode::SuperChip => MEMORY_LENGTH,
+            Mode::XoChip => XO_MEMORY_LENGTH,
+        }
+    }
+}
@@ -1,16 +1,27 @@
 use super::register::Register;
-use std::fmt::{Display, Formatter};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
 
 #[derive(Debug)]
 pub enum Opcode {
     SYS(u16),                    // 0nnn - SYS addr
     CLS,                         // 00E0 - CLS
     RET,                         // 00EE - RET
+    SCD(u8),                     // 00Cn - SCD nibble
+    SCU(u8),                     // 00Dn - SCU nibble
+    SCR,                         // 00FB - SCR
+    SCL,                         // 00FC - SCL
+    EXIT,                        // 00FD - EXIT
+    LOW,                         // 00FE - LOW
+    HIGH,                        // 00FF - HIGH
     JP(u16),                     // 1nnn - JP addr
     CALL(u16),                   // 2nnn - CALL addr
     SEI(Register, u8),           // 3xkk - SE Vx, byte
     SNEI(Register, u8),          // 4xkk - SNE Vx, byte
     SE(Register, Register),      // 5xy0 - SE Vx, Vy
+    SAVE(Register, Register),    // 5xy2 - SAVE Vx - Vy
+    LOAD(Register, Register),    // 5xy3 - LOAD Vx - Vy
     LDI(Register, u8),           // 6xkk - LD Vx, byte
     ADDI(Register, u8),          // 7xkk - ADD Vx, byte
     LD(Register, Register),      // 8xy0 - LD Vx, Vy
@@ -18,12 +29,15 @@ pub enum Opcode {
     AND(Register, Register),     // 8xy2 - AND Vx, Vy
     XOR(Register, Register),     // 8xy3 - XOR Vx, Vy
     ADD(Register, Register),     // 8xy4 - ADD Vx, Vy
-    SUBR(Register, Register),    // 8xy5 - SUB Vx,
-    SHR(Register),               // 8xy6 - SHR Vx {, Vy}
+    SUBR(Register, Register),    // 8xy5 - SUB Vx, Vy
+    SHR(Register, Register),     // 8xy6 - SHR Vx {, Vy}
     SUBN(Register, Register),    // 8xy7 - SUBN Vx, Vy
-    SHL(Register),               // 8xyE - SHL Vx {, Vy}
+    SHL(Register, Register),     // 8xyE - SHL Vx {, Vy}
     SNE(Register, Register),     // 9xy0 - SNE Vx, Vy
     LDII(u16),                   // Annn - LD I, addr
+    LDIL(u16),                   // F000 nnnn - LD I, long addr
+    PLANE(u8),                   // Fn01 - PLANE n
+    AUDIO,                       // F002 - AUDIO
     JPA(u16),                    // Bnnn - JP V0, addr
     RND(Register, u8),           // Cxkk - RND Vx, byte
     DRW(Register, Register, u8), // Dxyn - DRW Vx, Vy, nibble
@@ -33,53 +47,194 @@ pub enum Opcode {
     LDK(Register),               // Fx0A - LD Vx, K
     LDDTV(Register),             // Fx15 - LD DT, Vx
     LDST(Register),              // Fx18 - LD ST, Vx
+    PITCH(Register),             // Fx3A - PITCH Vx
     ADDRI(Register),             // Fx1E - ADD I, Vx
     LDF(Register),               // Fx29 - LD F, Vx
+    LDHF(Register),              // Fx30 - LD HF, Vx
     LDB(Register),               // Fx33 - LD B, Vx
     LDIM(Register),              // Fx55 - LD [I], Vx
     LDMI(Register),              // Fx65 - LD Vx, [I]
+    LDRV(Register),              // Fx75 - LD R, Vx
+    LDVR(Register),              // Fx85 - LD Vx, R
 }
 
+impl Opcode {
+    /// Name of the variant, e.g. `DRW`, for grouping instructions by kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opcode::SYS(..) => "SYS",
+            Opcode::CLS => "CLS",
+            Opcode::RET => "RET",
+            Opcode::SCD(..) => "SCD",
+            Opcode::SCU(..) => "SCU",
+            Opcode::SCR => "SCR",
+            Opcode::SCL => "SCL",
+            Opcode::EXIT => "EXIT",
+            Opcode::LOW => "LOW",
+            Opcode::HIGH => "HIGH",
+            Opcode::JP(..) => "JP",
+            Opcode::CALL(..) => "CALL",
+            Opcode::SEI(..) => "SEI",
+            Opcode::SNEI(..) => "SNEI",
+            Opcode::SE(..) => "SE",
+            Opcode::SAVE(..) => "SAVE",
+            Opcode::LOAD(..) => "LOAD",
+            Opcode::LDI(..) => "LDI",
+            Opcode::ADDI(..) => "ADDI",
+            Opcode::LD(..) => "LD",
+            Opcode::OR(..) => "OR",
+            Opcode::AND(..) => "AND",
+            Opcode::XOR(..) => "XOR",
+            Opcode::ADD(..) => "ADD",
+            Opcode::SUBR(..) => "SUBR",
+            Opcode::SHR(..) => "SHR",
+            Opcode::SUBN(..) => "SUBN",
+            Opcode::SHL(..) => "SHL",
+            Opcode::SNE(..) => "SNE",
+            Opcode::LDII(..) => "LDII",
+            Opcode::LDIL(..) => "LDIL",
+            Opcode::PLANE(..) => "PLANE",
+            Opcode::AUDIO => "AUDIO",
+            Opcode::JPA(..) => "JPA",
+            Opcode::RND(..) => "RND",
+            Opcode::DRW(..) => "DRW",
+            Opcode::SKP(..) => "SKP",
+            Opcode::SKNP(..) => "SKNP",
+            Opcode::LDVDT(..) => "LDVDT",
+            Opcode::LDK(..) => "LDK",
+            Opcode::LDDTV(..) => "LDDTV",
+            Opcode::LDST(..) => "LDST",
+            Opcode::PITCH(..) => "PITCH",
+            Opcode::ADDRI(..) => "ADDRI",
+            Opcode::LDF(..) => "LDF",
+            Opcode::LDHF(..) => "LDHF",
+            Opcode::LDB(..) => "LDB",
+            Opcode::LDIM(..) => "LDIM",
+            Opcode::LDMI(..) => "LDMI",
+            Opcode::LDRV(..) => "LDRV",
+            Opcode::LDVR(..) => "LDVR",
+        }
+    }
+
+    /// Machine code of the instruction, the inverse of `CPU::decode`. Only
+    /// F000 nnnn takes 4 bytes, everything else 2.
+    pub fn encode(&self) -> Vec<u8> {
+        let xy =
+            |x: &Register, y: &Register, low: u16| (x.id as u16) << 8 | (y.id as u16) << 4 | low;
+        let xkk = |x: &Register, kk: u8| (x.id as u16) << 8 | kk as u16;
+        let x = |x: &Register| (x.id as u16) << 8;
+        let word = match self {
+            Opcode::SYS(addr) => addr & 0xfff,
+            Opcode::CLS => 0x00e0,
+            Opcode::RET => 0x00ee,
+            Opcode::SCD(n) => 0x00c0 | (*n as u16 & 0xf),
+            Opcode::SCU(n) => 0x00d0 | (*n as u16 & 0xf),
+            Opcode::SCR => 0x00fb,
+            Opcode::SCL => 0x00fc,
+            Opcode::EXIT => 0x00fd,
+            Opcode::LOW => 0x00fe,
+            Opcode::HIGH => 0x00ff,
+            Opcode::JP(addr) => 0x1000 | (addr & 0xfff),
+            Opcode::CALL(addr) => 0x2000 | (addr & 0xfff),
+            Opcode::SEI(reg, byte) => 0x3000 | xkk(reg, *byte),
+            Opcode::SNEI(reg, byte) => 0x4000 | xkk(reg, *byte),
+            Opcode::SE(reg1, reg2) => 0x5000 | xy(reg1, reg2, 0x0),
+            Opcode::SAVE(reg1, reg2) => 0x5000 | xy(reg1, reg2, 0x2),
+            Opcode::LOAD(reg1, reg2) => 0x5000 | xy(reg1, reg2, 0x3),
+            Opcode::LDI(reg, byte) => 0x6000 | xkk(reg, *byte),
+            Opcode::ADDI(reg, byte) => 0x7000 | xkk(reg, *byte),
+            Opcode::LD(reg1, reg2) => 0x8000 | xy(reg1, reg2, 0x0),
+            Opcode::OR(reg1, reg2) => 0x8000 | xy(reg1, reg2, 0x1),
+            Opcode::AND(reg1, reg2) => 0x8000 | xy(reg1, reg2, 0x2),
+            Opcode::XOR(reg1, reg2) => 0x8000 | xy(reg1, reg2, 0x3),
+            Opcode::ADD(reg1, reg2) => 0x8000 | xy(reg1, reg2, 0x4),
+            Opcode::SUBR(reg1, reg2) => 0x8000 | xy(reg1, reg2, 0x5),
+            Opcode::SHR(reg1, reg2) => 0x8000 | xy(reg1, reg2, 0x6),
+            Opcode::SUBN(reg1, reg2) => 0x8000 | xy(reg1, reg2, 0x7),
+            Opcode::SHL(reg1, reg2) => 0x8000 | xy(reg1, reg2, 0xe),
+            Opcode::SNE(reg1, reg2) => 0x9000 | xy(reg1, reg2, 0x0),
+            Opcode::LDII(addr) => 0xa000 | (addr & 0xfff),
+            Opcode::LDIL(addr) => return vec![0xf0, 0x00, (addr >> 8) as u8, *addr as u8],
+            Opcode::PLANE(n) => 0xf001 | (*n as u16 & 0xf) << 8,
+            Opcode::AUDIO => 0xf002,
+            Opcode::JPA(addr) => 0xb000 | (addr & 0xfff),
+            Opcode::RND(reg, byte) => 0xc000 | xkk(reg, *byte),
+            Opcode::DRW(reg1, reg2, n) => 0xd000 | xy(reg1, reg2, *n as u16 & 0xf),
+            Opcode::SKP(reg) => 0xe09e | x(reg),
+            Opcode::SKNP(reg) => 0xe0a1 | x(reg),
+            Opcode::LDVDT(reg) => 0xf007 | x(reg),
+            Opcode::LDK(reg) => 0xf00a | x(reg),
+            Opcode::LDDTV(reg) => 0xf015 | x(reg),
+            Opcode::LDST(reg) => 0xf018 | x(reg),
+            Opcode::PITCH(reg) => 0xf03a | x(reg),
+            Opcode::ADDRI(reg) => 0xf01e | x(reg),
+            Opcode::LDF(reg) => 0xf029 | x(reg),
+            Opcode::LDHF(reg) => 0xf030 | x(reg),
+            Opcode::LDB(reg) => 0xf033 | x(reg),
+            Opcode::LDIM(reg) => 0xf055 | x(reg),
+            Opcode::LDMI(reg) => 0xf065 | x(reg),
+            Opcode::LDRV(reg) => 0xf075 | x(reg),
+            Opcode::LDVR(reg) => 0xf085 | x(reg),
+        };
+        word.to_be_bytes().to_vec()
+    }
+}
+
+// Mnemonics in the usual CHIP-8 assembly syntax, with hexadecimal addresses and bytes
 impl Display for Opcode {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
-            Opcode::SYS(addr) => write!(f, "SYS \t{}", addr),
+            Opcode::SYS(addr) => write!(f, "SYS 0x{:03x}", addr),
             Opcode::CLS => write!(f, "CLS"),
             Opcode::RET => write!(f, "RET"),
-            Opcode::JP(addr) => write!(f, "JP \t\t{}", addr),
-            Opcode::CALL(addr) => write!(f, "CALL \t{}", addr),
-            Opcode::SEI(reg, byte) => write!(f, "SEI \tR{:x} \t{}", reg.id, byte),
-            Opcode::SNEI(reg, byte) => write!(f, "SNEI \tR{:x} \t{}", reg.id, byte),
-            Opcode::SE(reg1, reg2) => write!(f, "SE \tR{:x} \tR{:x}", reg1.id, reg2.id),
-            Opcode::LDI(reg, byte) => write!(f, "LDI \tR{:x} \t{}", reg.id, byte),
-            Opcode::ADDI(reg, byte) => write!(f, "ADDI \tR{:x} \t{}", reg.id, byte),
-            Opcode::LD(reg1, reg2) => write!(f, "LD \tR{:x} \tR{:x}", reg1.id, reg2.id),
-            Opcode::OR(reg1, reg2) => write!(f, "OR \tR{:x} \tR{:x}", reg1.id, reg2.id),
-            Opcode::AND(reg1, reg2) => write!(f, "AND \tR{:x} \tR{:x}", reg1.id, reg2.id),
-            Opcode::XOR(reg1, reg2) => write!(f, "XOR \tR{:x} \tR{:x}", reg1.id, reg2.id),
-            Opcode::ADD(reg1, reg2) => write!(f, "ADD \tR{:x} \tR{:x}", reg1.id, reg2.id),
-            Opcode::SUBR(reg1, reg2) => write!(f, "SUBR \tR{:x} \tR{:x}", reg1.id, reg2.id),
-            Opcode::SHR(reg) => write!(f, "SHR \tR{:x}", reg.id),
-            Opcode::SUBN(reg1, reg2) => write!(f, "SUBN \tR{:x} \tR{:x}", reg1.id, reg2.id),
-            Opcode::SHL(reg) => write!(f, "SHL \tR{:x}", reg.id),
-            Opcode::SNE(reg1, reg2) => write!(f, "SNE \tR{:x} \tR{:x}", reg1.id, reg2.id),
-            Opcode::LDII(addr) => write!(f, "LDII \t{}", addr),
-            Opcode::JPA(addr) => write!(f, "JPA \t{}", addr),
-            Opcode::RND(reg, byte) => write!(f, "RND \tR{:x} \t{}", reg.id, byte),
-            Opcode::DRW(reg1, reg2, byte) => {
-                write!(f, "DRW  \tR{:x}  \tR{:x} \t{}", reg1.id, reg2.id, byte)
-            }
-            Opcode::SKP(reg) => write!(f, "SKP \tR{:x}", reg.id),
-            Opcode::SKNP(reg) => write!(f, "SKNP \tR{:x}", reg.id),
-            Opcode::LDVDT(reg) => write!(f, "LDVDT \tR{:x}", reg.id),
-            Opcode::LDK(reg) => write!(f, "LDK \tR{:x}", reg.id),
-            Opcode::LDDTV(reg) => write!(f, "LDDTV \tR{:x}", reg.id),
-            Opcode::LDST(reg) => write!(f, "LDST \tR{:x}", reg.id),
-            Opcode::ADDRI(reg) => write!(f, "ADDRI \tR{:x}", reg.id),
-            Opcode::LDF(reg) => write!(f, "LDF \tR{:x}", reg.id),
-            Opcode::LDB(reg) => write!(f, "LDB \tR{:x}", reg.id),
-            Opcode::LDIM(reg) => write!(f, "LDIM \tR{:x}", reg.id),
-            Opcode::LDMI(reg) => write!(f, "LDMI \tR{:x}", reg.id),
+            Opcode::SCD(n) => write!(f, "SCD {}", n),
+            Opcode::SCU(n) => write!(f, "SCU {}", n),
+            Opcode::SCR => write!(f, "SCR"),
+            Opcode::SCL => write!(f, "SCL"),
+            Opcode::EXIT => write!(f, "EXIT"),
+            Opcode::LOW => write!(f, "LOW"),
+            Opcode::HIGH => write!(f, "HIGH"),
+            Opcode::JP(addr) => write!(f, "JP 0x{:03x}", addr),
+            Opcode::CALL(addr) => write!(f, "CALL 0x{:03x}", addr),
+            Opcode::SEI(reg, byte) => write!(f, "SE {}, 0x{:02x}", reg, byte),
+            Opcode::SNEI(reg, byte) => write!(f, "SNE {}, 0x{:02x}", reg, byte),
+            Opcode::SE(reg1, reg2) => write!(f, "SE {}, {}", reg1, reg2),
+            Opcode::SAVE(reg1, reg2) => write!(f, "SAVE {} - {}", reg1, reg2),
+            Opcode::LOAD(reg1, reg2) => write!(f, "LOAD {} - {}", reg1, reg2),
+            Opcode::LDI(reg, byte) => write!(f, "LD {}, 0x{:02x}", reg, byte),
+            Opcode::ADDI(reg, byte) => write!(f, "ADD {}, 0x{:02x}", reg, byte),
+            Opcode::LD(reg1, reg2) => write!(f, "LD {}, {}", reg1, reg2),
+            Opcode::OR(reg1, reg2) => write!(f, "OR {}, {}", reg1, reg2),
+            Opcode::AND(reg1, reg2) => write!(f, "AND {}, {}", reg1, reg2),
+            Opcode::XOR(reg1, reg2) => write!(f, "XOR {}, {}", reg1, reg2),
+            Opcode::ADD(reg1, reg2) => write!(f, "ADD {}, {}", reg1, reg2),
+            Opcode::SUBR(reg1, reg2) => write!(f, "SUB {}, {}", reg1, reg2),
+            Opcode::SHR(reg1, reg2) => write!(f, "SHR {}, {}", reg1, reg2),
+            Opcode::SUBN(reg1, reg2) => write!(f, "SUBN {}, {}", reg1, reg2),
+            Opcode::SHL(reg1, reg2) => write!(f, "SHL {}, {}", reg1, reg2),
+            Opcode::SNE(reg1, reg2) => write!(f, "SNE {}, {}", reg1, reg2),
+            Opcode::LDII(addr) => write!(f, "LD I, 0x{:03x}", addr),
+            Opcode::LDIL(addr) => write!(f, "LD I, LONG 0x{:04x}", addr),
+            Opcode::PLANE(n) => write!(f, "PLANE {}", n),
+            Opcode::AUDIO => write!(f, "AUDIO"),
+            Opcode::JPA(addr) => write!(f, "JP V0, 0x{:03x}", addr),
+            Opcode::RND(reg, byte) => write!(f, "RND {}, 0x{:02x}", reg, byte),
+            Opcode::DRW(reg1, reg2, n) => write!(f, "DRW {}, {}, {}", reg1, reg2, n),
+            Opcode::SKP(reg) => write!(f, "SKP {}", reg),
+            Opcode::SKNP(reg) => write!(f, "SKNP {}", reg),
+            Opcode::LDVDT(reg) => write!(f, "LD {}, DT", reg),
+            Opcode::LDK(reg) => write!(f, "LD {}, K", reg),
+            Opcode::LDDTV(reg) => write!(f, "LD DT, {}", reg),
+            Opcode::LDST(reg) => write!(f, "LD ST, {}", reg),
+            Opcode::PITCH(reg) => write!(f, "PITCH {}", reg),
+            Opcode::ADDRI(reg) => write!(f, "ADD I, {}", reg),
+            Opcode::LDF(reg) => write!(f, "LD F, {}", reg),
+            Opcode::LDHF(reg) => write!(f, "LD HF, {}", reg),
+            Opcode::LDB(reg) => write!(f, "LD B, {}", reg),
+            Opcode::LDIM(reg) => write!(f, "LD [I], {}", reg),
+            Opcode::LDMI(reg) => write!(f, "LD {}, [I]", reg),
+            Opcode::LDRV(reg) => write!(f, "LD R, {}", reg),
+            Opcode::LDVR(reg) => write!(f, "LD {}, R", reg),
         }
     }
 }
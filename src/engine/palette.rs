@@ -0,0 +1,71 @@
+/// Colours, as 0RGB, of the four pixel values: 0 for off, then one bit per
+/// XO-CHIP plane, so 1 is the first plane, 2 the second and 3 both.
+/// CHIP-8 and SUPER-CHIP only draw 0 and 1.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Palette {
+    pub colors: [u32; 4],
+}
+
+impl Palette {
+    pub const DEFAULT: Palette = Palette::two_tone(0x000000, 0xffffff);
+
+    /// Monochrome monitors
+    pub const AMBER: Palette = Palette::two_tone(0x1a0f00, 0xffb000);
+    pub const GREEN: Palette = Palette::two_tone(0x051a0a, 0x33ff66);
+
+    /// Dark pixels on a pale green LCD
+    pub const LCD: Palette = Palette::two_tone(0xc7f0d8, 0x43523d);
+
+    /// Octo's colours, which XO-CHIP ROMs are usually drawn for
+    pub const OCTO: Palette = Palette {
+        colors: [0x996600, 0xffcc00, 0xff6600, 0x662200],
+    };
+
+    /// A background and foreground, with the second plane and the overlap
+    /// two thirds and one third of the way to the foreground
+    pub const fn two_tone(background: u32, foreground: u32) -> Palette {
+        Palette {
+            colors: [
+                background,
+                foreground,
+                mix(background, foreground, 2),
+                mix(background, foreground, 1),
+            ],
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        match name {
+            "default" => Some(Self::DEFAULT),
+            "amber" => Some(Self::AMBER),
+            "green" => Some(Self::GREEN),
+            "lcd" => Some(Self::LCD),
+            "octo" => Some(Self::OCTO),
+            _ => None,
+        }
+    }
+
+    /// Colour of a pixel from `VM::pixels`
+    pub fn color(&self, pixel: u8) -> u32 {
+        self.colors[pixel as usize & 0x3]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// `thirds` of the way from `from` to `to`, channel by channel
+const fn mix(from: u32, to: u32, thirds: u32) -> u32 {
+    let mut color = 0;
+    let mut shift = 0;
+    while shift < 24 {
+        let from = (from >> shift) & 0xff;
+        let to = (to >> shift) & 0xff;
+        color |= ((from * (3 - thirds) + to * thirds) / 3) << shift;
+        shift += 8;
+    }
+    color
+}
@@ -0,0 +1,168 @@
+use super::cpu::CPU;
+use super::memory::Memory;
+use super::opcode::Opcode;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+const HOT_ADDRESSES: usize = 20;
+const HOT_LOOPS: usize = 10;
+
+/// Time and instructions between two events, like frames or draws
+#[derive(Default)]
+struct Intervals {
+    last: Option<(Instant, u64)>,
+    count: u64,
+    instructions: u64,
+    total: Duration,
+    shortest: Option<Duration>,
+    longest: Duration,
+}
+
+impl Intervals {
+    fn record(&mut self, instructions: u64) {
+        let now = Instant::now();
+        if let Some((last, last_instructions)) = self.last {
+            let elapsed = now - last;
+            self.count += 1;
+            self.instructions += instructions - last_instructions;
+            self.total += elapsed;
+            self.shortest = Some(
+                self.shortest
+                    .map_or(elapsed, |shortest| shortest.min(elapsed)),
+            );
+            self.longest = self.longest.max(elapsed);
+        }
+        self.last = Some((now, instructions));
+    }
+
+    fn report(&self, name: &str) -> String {
+        if self.count == 0 {
+            return format!("{}: fewer than two\n", name);
+        }
+        let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        format!(
+            "{}: every {:.2}ms on average ({:.2}ms to {:.2}ms), {:.1} instructions apart\n",
+            name,
+            milliseconds(self.total) / self.count as f64,
+            milliseconds(self.shortest.unwrap_or_default()),
+            milliseconds(self.longest),
+            self.instructions as f64 / self.count as f64
+        )
+    }
+}
+
+/// Counts what a ROM executes, to find hot loops and busy waits. Frames are
+/// the calls to `VM::tick_timers`.
+#[derive(Default)]
+pub struct Profile {
+    instructions: u64,
+    frames: u64,
+    addresses: BTreeMap<u16, u64>,
+    opcodes: BTreeMap<&'static str, u64>,
+    // Backward jumps close loops, by start and end of the loop
+    loops: BTreeMap<(u16, u16), u64>,
+    // Number of calls to every subroutine
+    calls: BTreeMap<u16, u64>,
+    returns: u64,
+    frame_intervals: Intervals,
+    draw_intervals: Intervals,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(super) fn instruction(&mut self, address: u16, opcode: &Opcode) {
+        self.instructions += 1;
+        *self.addresses.entry(address).or_insert(0) += 1;
+        *self.opcodes.entry(opcode.name()).or_insert(0) += 1;
+        match opcode {
+            Opcode::JP(target) if *target <= address => {
+                *self.loops.entry((*target, address)).or_insert(0) += 1
+            }
+            Opcode::CALL(target) => *self.calls.entry(*target).or_insert(0) += 1,
+            Opcode::RET => self.returns += 1,
+            Opcode::DRW(..) => self.draw_intervals.record(self.instructions),
+            _ => {}
+        }
+    }
+
+    pub(super) fn frame(&mut self) {
+        self.frames += 1;
+        self.frame_intervals.record(self.instructions);
+    }
+
+    /// Addresses are disassembled from the current memory
+    pub fn report(&self, cpu: &CPU, memory: &Memory) -> String {
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        let mut report = String::new();
+        writeln!(
+            report,
+            "{} instructions in {} frames",
+            self.instructions, self.frames
+        )
+        .unwrap();
+        report.push_str(&self.frame_intervals.report("Frames"));
+        report.push_str(&self.draw_intervals.report("Draws"));
+
+        let mut loops: Vec<(&(u16, u16), &u64)> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        report.push_str("\nHottest loops:\n");
+        for ((start, end), count) in loops.into_iter().take(HOT_LOOPS) {
+            writeln!(
+                report,
+                "    {:04x}-{:04x}  {:>10} iterations",
+                start, end, count
+            )
+            .unwrap();
+        }
+
+        let mut hottest: Vec<(&u16, &u64)> = self.addresses.iter().collect();
+        hottest.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        report.push_str("\nHottest addresses:\n");
+        for (address, count) in hottest.into_iter().take(HOT_ADDRESSES) {
+            let instruction = cpu
+                .instruction_at(memory, *address)
+                .map_or_else(|e| e.to_string(), |(opcode, _)| opcode.to_string());
+            writeln!(
+                report,
+                "    {:04x}  {:>10}  {:>5.1}%  {}",
+                address,
+                count,
+                percent(*count),
+                instruction
+            )
+            .unwrap();
+        }
+
+        let mut opcodes: Vec<(&&str, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        report.push_str("\nInstructions:\n");
+        for (name, count) in opcodes {
+            writeln!(
+                report,
+                "    {:<6} {:>10}  {:>5.1}%",
+                name,
+                count,
+                percent(*count)
+            )
+            .unwrap();
+        }
+
+        let mut calls: Vec<(&u16, &u64)> = self.calls.iter().collect();
+        calls.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(
+            report,
+            "\nSubroutines: {} calls, {} returns",
+            self.calls.values().sum::<u64>(),
+            self.returns
+        )
+        .unwrap();
+        for (address, count) in calls {
+            writeln!(report, "    {:04x}  {:>10}", address, count).unwrap();
+        }
+        report
+    }
+}
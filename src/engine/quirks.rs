@@ -0,0 +1,141 @@
+/// Behaviour of the instructions that were implemented differently across
+/// CHIP-8 interpreters.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Quirks {
+    /// 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// Fx55/Fx65 leave I pointing past the last register stored/loaded.
+    pub load_store_increments_i: bool,
+    /// Bnnn jumps to nnn + Vx (x being the high nibble of nnn) instead of nnn + V0.
+    pub jump_uses_vx: bool,
+    /// 8xy1/8xy2/8xy3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Dxyn clips sprites at the edges of the screen instead of wrapping them.
+    pub clip_sprites: bool,
+    /// Dxy0 draws an 8x16 sprite in low resolution instead of a 16x16 one.
+    pub lores_sprites_8x16: bool,
+    /// Dxyn in high resolution sets VF to the number of rows that collided or
+    /// were clipped at the bottom of the screen instead of 1.
+    pub vf_counts_rows: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        clip_sprites: true,
+        lores_sprites_8x16: false,
+        vf_counts_rows: false,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: true,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        lores_sprites_8x16: false,
+        vf_counts_rows: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        lores_sprites_8x16: true,
+        vf_counts_rows: true,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+        lores_sprites_8x16: false,
+        vf_counts_rows: false,
+    };
+
+    pub fn to_bits(self) -> u8 {
+        self.shift_uses_vy as u8
+            | (self.load_store_increments_i as u8) << 1
+            | (self.jump_uses_vx as u8) << 2
+            | (self.logic_resets_vf as u8) << 3
+            | (self.clip_sprites as u8) << 4
+            | (self.lores_sprites_8x16 as u8) << 5
+            | (self.vf_counts_rows as u8) << 6
+    }
+
+    pub fn from_bits(bits: u8) -> Quirks {
+        Quirks {
+            shift_uses_vy: bits & 0x1 != 0,
+            load_store_increments_i: bits & 0x2 != 0,
+            jump_uses_vx: bits & 0x4 != 0,
+            logic_resets_vf: bits & 0x8 != 0,
+            clip_sprites: bits & 0x10 != 0,
+            lores_sprites_8x16: bits & 0x20 != 0,
+            vf_counts_rows: bits & 0x40 != 0,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" | "cosmac-vip" => Some(Self::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Self::CHIP_48),
+            "schip" | "super-chip" => Some(Self::SUPER_CHIP),
+            "xochip" | "xo-chip" | "octo" => Some(Self::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::COSMAC_VIP
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::mode::Mode;
+
+    fn flags(quirks: Quirks) -> [bool; 7] {
+        [
+            quirks.shift_uses_vy,
+            quirks.load_store_increments_i,
+            quirks.jump_uses_vx,
+            quirks.logic_resets_vf,
+            quirks.clip_sprites,
+            quirks.lores_sprites_8x16,
+            quirks.vf_counts_rows,
+        ]
+    }
+
+    #[test]
+    fn presets() {
+        let presets = [
+            ("vip", [true, true, false, true, true, false, false]),
+            ("chip48", [false, true, true, false, true, false, false]),
+            ("schip", [false, false, true, false, true, true, true]),
+            ("xochip", [true, true, false, false, false, false, false]),
+        ];
+        for (name, expected) in presets {
+            let quirks = Quirks::from_name(name).unwrap();
+            assert_eq!(flags(quirks), expected, "{}", name);
+            assert_eq!(Quirks::from_bits(quirks.to_bits()), quirks, "{}", name);
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(Quirks::default(), Quirks::COSMAC_VIP);
+        assert_eq!(Mode::Chip8.default_quirks(), Quirks::COSMAC_VIP);
+        assert_eq!(Mode::SuperChip.default_quirks(), Quirks::SUPER_CHIP);
+        assert_eq!(Mode::XoChip.default_quirks(), Quirks::XO_CHIP);
+    }
+}
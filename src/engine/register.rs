@@ -1,4 +1,6 @@
-use std::fmt::{Display, Formatter};
+use super::error::EmulatorError;
+use super::state::{StateReader, StateWriter};
+use core::fmt::{Display, Formatter};
 
 #[derive(Debug)]
 pub struct Register {
@@ -6,21 +8,21 @@ pub struct Register {
 }
 
 impl Register {
-    pub fn new(id: u16) -> Register {
+    pub fn new(id: u16) -> Result<Register, EmulatorError> {
         if id > 15 {
-            panic!("Trying to create register with id {}", id);
+            return Err(EmulatorError::InvalidRegister(id));
         }
-        Self { id: id as u8 }
+        Ok(Self { id: id as u8 })
     }
 
     pub fn vf() -> Register {
-        Self::new(0xf)
+        Self { id: 0xf }
     }
 }
 
 impl Display for Register {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "R{:x}", self.id)
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "V{:X}", self.id)
     }
 }
 
@@ -31,6 +33,12 @@ pub struct Registers {
     pub st: u8,
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Registers {
     pub fn new() -> Self {
         Self {
@@ -49,6 +57,28 @@ impl Registers {
         self.registers[register.id as usize] = value
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.registers
+            .iter()
+            .for_each(|value| writer.put_u8(*value));
+        writer.put_u16(self.i);
+        writer.put_u8(self.dt);
+        writer.put_u8(self.st)
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Self, EmulatorError> {
+        let mut registers = [0; 16];
+        for value in registers.iter_mut() {
+            *value = reader.get_u8()?;
+        }
+        Ok(Self {
+            registers,
+            i: reader.get_u16()?,
+            dt: reader.get_u8()?,
+            st: reader.get_u8()?,
+        })
+    }
+
     pub fn tick(&mut self) {
         if self.dt > 0 {
             self.dt -= 1;
@@ -60,14 +90,14 @@ impl Registers {
 }
 
 impl Display for Registers {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "Registers:")?;
         for (i, value) in self.registers.iter().enumerate() {
-            write!(f, "{}: {} \t", Register::new(i as u16), value)?;
+            write!(f, "{}: {:02x} \t", Register { id: i as u8 }, value)?;
         }
         writeln!(f)?;
-        write!(f, "I: {} \t", self.i)?;
-        write!(f, "DT: {} \t", self.dt)?;
-        write!(f, "ST: {} \t", self.st)
+        write!(f, "I: {:04x} \t", self.i)?;
+        write!(f, "DT: {:02x} \t", self.dt)?;
+        write!(f, "ST: {:02x} \t", self.st)
     }
 }
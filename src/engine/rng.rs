@@ -0,0 +1,58 @@
+#[cfg(feature = "std")]
+use rand::{thread_rng, Rng as _};
+
+/// Source of the bytes returned by RND. The state is opaque to the VM and only
+/// stored in save states so that a restored VM keeps producing the same bytes.
+pub trait RandomSource: Send {
+    fn next_u8(&mut self) -> u8;
+    fn state(&self) -> u64;
+    fn restore(&mut self, state: u64);
+}
+
+/// Seed of a new VM's random source
+#[cfg(feature = "std")]
+pub fn random_seed() -> u64 {
+    thread_rng().gen()
+}
+
+/// Without std there is no entropy to seed from, every VM starts out the
+/// same unless `VM::set_seed` is called
+#[cfg(not(feature = "std"))]
+pub fn random_seed() -> u64 {
+    0x853c_49e6_748f_ea9b
+}
+
+/// xorshift64*, the default source
+pub struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Self {
+        let mut xorshift = Self { state: 0 };
+        xorshift.restore(seed);
+        xorshift
+    }
+}
+
+impl RandomSource for Xorshift {
+    fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn restore(&mut self, state: u64) {
+        // An all zero state would only ever produce zeros
+        self.state = if state == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            state
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use super::error::EmulatorError;
+use super::state::{StateReader, StateWriter};
+use super::vm::{HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH};
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const PLANES: usize = 2;
+
+// Every pixel holds one bit per plane, so the colour of a pixel is an index
+// into a 4 colour palette.
+pub struct Screen {
+    hires: bool,
+    planes: u8,
+    pixels: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            hires: false,
+            planes: 0x1,
+            pixels: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_bool(self.hires);
+        writer.put_u8(self.planes);
+        writer.put_bytes(&self.pixels)
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Self, EmulatorError> {
+        let mut screen = Self::new();
+        screen.set_hires(reader.get_bool()?);
+        screen.select_planes(reader.get_u8()?);
+        let pixels = reader.get_bytes()?;
+        if pixels.len() != screen.pixels.len() {
+            return Err(EmulatorError::InvalidSaveState(
+                "screen size does not match resolution",
+            ));
+        }
+        screen.pixels.copy_from_slice(pixels);
+        Ok(screen)
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            HEIGHT
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = vec![0; self.width() * self.height()];
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0x3
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn clear(&mut self) {
+        let planes = self.planes;
+        self.pixels.iter_mut().for_each(|x| *x &= !planes)
+    }
+
+    // Each row holds up to 16 pixels, most significant bit first, and is
+    // drawn into a single plane. Returns the number of rows that turned a lit
+    // pixel off and, when clipping, the number of rows below the screen.
+    pub fn draw(
+        &mut self,
+        x: usize,
+        y: usize,
+        rows: &[u16],
+        plane: u8,
+        clip: bool,
+    ) -> (usize, usize) {
+        let (width, height) = (self.width(), self.height());
+        let x_offset = x % width;
+        let y_offset = y % height;
+        let mut collisions = 0;
+        let mut clipped = 0;
+        for (ys, row) in rows.iter().enumerate() {
+            let y = y_offset + ys;
+            if clip && y >= height {
+                clipped += 1;
+                continue;
+            }
+            let mut collision = false;
+            for xs in 0..16 {
+                if (row & (0x8000 >> xs)) == 0 {
+                    continue;
+                }
+                let x = x_offset + xs;
+                if clip && x >= width {
+                    continue;
+                }
+                let l = (y % height) * width + x % width;
+                if self.pixels[l] & plane != 0 {
+                    collision = true
+                }
+                self.pixels[l] ^= plane;
+            }
+            collisions += collision as usize;
+        }
+        (collisions, clipped)
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        let shift = (n * self.width()).min(self.pixels.len());
+        self.scroll(|pixels| {
+            pixels.rotate_right(shift);
+            pixels[..shift].iter_mut().for_each(|x| *x = 0)
+        })
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        let shift = (n * self.width()).min(self.pixels.len());
+        self.scroll(|pixels| {
+            let length = pixels.len();
+            pixels.rotate_left(shift);
+            pixels[length - shift..].iter_mut().for_each(|x| *x = 0)
+        })
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        let width = self.width();
+        let n = n.min(width);
+        self.scroll(|pixels| {
+            for row in pixels.chunks_mut(width) {
+                row.rotate_right(n);
+                row[..n].iter_mut().for_each(|x| *x = 0)
+            }
+        })
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        let width = self.width();
+        let n = n.min(width);
+        self.scroll(|pixels| {
+            for row in pixels.chunks_mut(width) {
+                row.rotate_left(n);
+                row[width - n..].iter_mut().for_each(|x| *x = 0)
+            }
+        })
+    }
+
+    // Scrolling only moves the selected planes, the others stay in place
+    fn scroll<F: FnOnce(&mut [u8])>(&mut self, shift: F) {
+        let mut scrolled = self.pixels.clone();
+        shift(&mut scrolled);
+        let planes = self.planes;
+        for (pixel, moved) in self.pixels.iter_mut().zip(scrolled) {
+            *pixel = (*pixel & !planes) | (moved & planes);
+        }
+    }
+}
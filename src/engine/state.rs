@@ -0,0 +1,110 @@
+use super::error::EmulatorError;
+use alloc::vec::Vec;
+
+pub const MAGIC: &[u8; 4] = b"C8ST";
+pub const VERSION: u8 = 2;
+
+// Save states are a flat big endian byte stream, written and read back in
+// the same order by each component.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        Self { data }
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.data.push(value)
+    }
+
+    pub fn put_bool(&mut self, value: bool) {
+        self.put_u8(value as u8)
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_be_bytes())
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_be_bytes())
+    }
+
+    // Length prefixed
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.data
+            .extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        self.data.extend_from_slice(bytes)
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, EmulatorError> {
+        let mut reader = Self { data, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(EmulatorError::InvalidSaveState("not a save state"));
+        }
+        let version = reader.get_u8()?;
+        if version != VERSION {
+            return Err(EmulatorError::UnsupportedSaveStateVersion(version));
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], EmulatorError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + length)
+            .ok_or(EmulatorError::InvalidSaveState("truncated"))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, EmulatorError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool, EmulatorError> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(EmulatorError::InvalidSaveState("invalid boolean")),
+        }
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16, EmulatorError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64, EmulatorError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn get_bytes(&mut self) -> Result<&'a [u8], EmulatorError> {
+        let mut length = [0; 4];
+        length.copy_from_slice(self.take(4)?);
+        self.take(u32::from_be_bytes(length) as usize)
+    }
+
+    pub fn finish(self) -> Result<(), EmulatorError> {
+        if self.position != self.data.len() {
+            return Err(EmulatorError::InvalidSaveState("trailing data"));
+        }
+        Ok(())
+    }
+}
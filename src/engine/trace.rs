@@ -0,0 +1,228 @@
+use super::cpu::CPU;
+use super::error::EmulatorError;
+use super::key::Key;
+use super::memory::Memory;
+use super::opcode::Opcode;
+use super::register::Register;
+use super::screen::Screen;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// Which instructions get logged, everything when both lists are empty
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub ranges: Vec<RangeInclusive<u16>>,
+    // Names of `Opcode` variants, e.g. DRW
+    pub opcodes: Vec<String>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, address: u16, opcode: &Opcode) -> bool {
+        let in_range =
+            self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&address));
+        let of_kind = self.opcodes.is_empty()
+            || self
+                .opcodes
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(opcode.name()));
+        in_range && of_kind
+    }
+}
+
+struct Snapshot {
+    v: [u8; 16],
+    i: u16,
+    dt: u8,
+    st: u8,
+}
+
+impl Snapshot {
+    fn of(cpu: &CPU) -> Self {
+        let registers = cpu.registers();
+        let mut v = [0; 16];
+        for (id, value) in v.iter_mut().enumerate() {
+            *value = registers.read(&Register { id: id as u8 });
+        }
+        Self {
+            v,
+            i: registers.i,
+            dt: registers.dt,
+            st: registers.st,
+        }
+    }
+}
+
+// Instructions that set VF as a flag, it is logged even when it doesn't change
+fn sets_flag(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::ADD(..)
+            | Opcode::SUBR(..)
+            | Opcode::SUBN(..)
+            | Opcode::SHR(..)
+            | Opcode::SHL(..)
+            | Opcode::DRW(..)
+    )
+}
+
+/// Logs executed instructions one per line, with the registers they changed:
+///
+/// ```text
+/// 0208  6e05       LD VE, 0x05                      VE=00->05
+/// 020c  8014       ADD V0, V1                       V0=fe->01 VF=00->01
+/// ```
+///
+/// There are no timestamps, so traces of the same run are identical.
+pub struct Trace {
+    writer: Box<dyn Write + Send>,
+    filter: TraceFilter,
+}
+
+impl Trace {
+    pub fn new(writer: Box<dyn Write + Send>, filter: TraceFilter) -> Self {
+        Self { writer, filter }
+    }
+
+    pub(super) fn step(
+        &mut self,
+        cpu: &mut CPU,
+        memory: &mut Memory,
+        screen: &mut Screen,
+        keys: &[Key],
+    ) -> Result<(), EmulatorError> {
+        let address = cpu.program_counter();
+        let (opcode, length) = match cpu.instruction_at(memory, address) {
+            Ok(decoded) if !cpu.is_halted() && self.filter.matches(address, &decoded.0) => decoded,
+            _ => return cpu.tick(memory, screen, keys),
+        };
+        let raw: Vec<String> = (0..length)
+            .step_by(2)
+            .map(|offset| {
+                let byte = |offset: u16| memory.peek(address.wrapping_add(offset)).unwrap_or(0);
+                format!("{:02x}{:02x}", byte(offset), byte(offset + 1))
+            })
+            .collect();
+        let before = Snapshot::of(cpu);
+        let result = cpu.tick(memory, screen, keys);
+        let after = Snapshot::of(cpu);
+
+        let mut changes = vec![];
+        for id in 0..0xf {
+            if before.v[id] != after.v[id] {
+                changes.push(format!(
+                    "V{:X}={:02x}->{:02x}",
+                    id, before.v[id], after.v[id]
+                ));
+            }
+        }
+        if before.v[0xf] != after.v[0xf] {
+            changes.push(format!("VF={:02x}->{:02x}", before.v[0xf], after.v[0xf]));
+        } else if sets_flag(&opcode) {
+            changes.push(format!("VF={:02x}", after.v[0xf]));
+        }
+        if before.i != after.i {
+            changes.push(format!("I={:04x}->{:04x}", before.i, after.i));
+        }
+        if before.dt != after.dt {
+            changes.push(format!("DT={:02x}->{:02x}", before.dt, after.dt));
+        }
+        if before.st != after.st {
+            changes.push(format!("ST={:02x}->{:02x}", before.st, after.st));
+        }
+        if let Err(error) = &result {
+            changes.push(format!("error: {}", error));
+        }
+        let line = format!(
+            "{:04x}  {:<9}  {:<32} {}",
+            address,
+            raw.join(" "),
+            opcode.to_string(),
+            changes.join(" ")
+        );
+        writeln!(self.writer, "{}", line.trim_end()).ok();
+        // The emulator usually stops right after an error
+        if result.is_err() {
+            self.writer.flush().ok();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::mode::Mode;
+    use crate::engine::vm::VM;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Log(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Log {
+        fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buffer)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // LD VE, 5; LD V1, 3; SHR V0, V1; LD I, 0x300; DRW V0, V1, 1
+    const ROM: [u8; 10] = [0x6e, 0x05, 0x61, 0x03, 0x80, 0x16, 0xa3, 0x00, 0xd0, 0x11];
+
+    fn trace(filter: TraceFilter) -> Vec<String> {
+        let log = Log::default();
+        let mode = Mode::Chip8;
+        let mut vm = VM::new(&ROM, mode, mode.default_quirks()).unwrap();
+        vm.set_trace(Some(Trace::new(Box::new(log.clone()), filter)));
+        for _ in 0..ROM.len() / 2 {
+            vm.step_instruction(&[]).unwrap();
+        }
+        let log = log.0.lock().unwrap();
+        String::from_utf8(log.clone())
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn logs_changed_registers() {
+        assert_eq!(
+            trace(TraceFilter::default()),
+            [
+                "0200  6e05       LD VE, 0x05                      VE=00->05",
+                "0202  6103       LD V1, 0x03                      V1=00->03",
+                "0204  8016       SHR V0, V1                       V0=00->01 VF=00->01",
+                "0206  a300       LD I, 0x300                      I=0000->0300",
+                "0208  d011       DRW V0, V1, 1                    VF=01->00",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let filter = TraceFilter {
+            ranges: vec![0x202..=0x204],
+            opcodes: vec![],
+        };
+        let lines = trace(filter);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0202") && lines[1].starts_with("0204"));
+
+        let filter = TraceFilter {
+            ranges: vec![],
+            opcodes: vec![String::from("drw"), String::from("LDII")],
+        };
+        let lines = trace(filter);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0206") && lines[1].starts_with("0208"));
+
+        let filter = TraceFilter {
+            ranges: vec![0x200..=0x205],
+            opcodes: vec![String::from("DRW")],
+        };
+        assert!(trace(filter).is_empty());
+    }
+}
@@ -1,10 +1,30 @@
-use super::cpu::CPU;
+use super::audio::Audio;
+use super::cpu::{CPU, STACK_SIZE};
+use super::error::EmulatorError;
 use super::key::Key;
-use super::memory::Memory;
+use super::memory::{Memory, WatchHit, Watchpoint};
+use super::mode::Mode;
+use super::palette::Palette;
+#[cfg(feature = "std")]
+use super::profile::Profile;
+use super::quirks::Quirks;
+use super::rng::RandomSource;
+use super::screen::Screen;
+use super::state::{StateReader, StateWriter};
+#[cfg(feature = "std")]
+use super::trace::Trace;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
+/// Resolution of the screen in low resolution mode
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
+/// Resolution of the SUPER-CHIP and XO-CHIP high resolution mode
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+/// Where ROMs are loaded and execution starts
 pub const PROGRAM_OFFSET: u16 = 0x200;
+pub const BIG_FONT_OFFSET: u16 = 0x50;
 
 pub static FONT_SET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -25,30 +45,232 @@ pub static FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+pub static BIG_FONT_SET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// A CHIP-8 machine: CPU, memory and screen. The VM doesn't keep time, the
+/// frontend calls `run_frame` 60 times per second with the keys held down.
 pub struct VM {
     cpu: CPU,
     memory: Memory,
-    frame_buffer: Vec<u32>,
+    screen: Screen,
+    #[cfg(feature = "std")]
+    trace: Option<Trace>,
+    #[cfg(feature = "std")]
+    profile: Option<Profile>,
 }
 
 impl VM {
-    pub fn new(rom: &[u8]) -> VM {
-        let mut memory = Memory::new();
-        memory.load(rom, PROGRAM_OFFSET);
-        memory.load(&FONT_SET, 0);
-        VM {
-            cpu: CPU::new(),
+    /// Loads `rom` at 0x200 along with the fonts
+    pub fn new(rom: &[u8], mode: Mode, quirks: Quirks) -> Result<VM, EmulatorError> {
+        let mut memory = Memory::new(mode.memory_length());
+        memory.load(rom, PROGRAM_OFFSET)?;
+        memory.load(&FONT_SET, 0)?;
+        memory.load(&BIG_FONT_SET, BIG_FONT_OFFSET)?;
+        Ok(VM {
+            cpu: CPU::new(mode, quirks),
             memory,
-            frame_buffer: vec![0; WIDTH * HEIGHT],
-        }
+            screen: Screen::new(),
+            #[cfg(feature = "std")]
+            trace: None,
+            #[cfg(feature = "std")]
+            profile: None,
+        })
+    }
+
+    /// Snapshot of the whole machine, restored by `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        self.cpu.save_state(&mut writer);
+        self.memory.save_state(&mut writer);
+        self.screen.save_state(&mut writer);
+        writer.finish()
+    }
+
+    /// Largest `save_state` can get in this mode, with a full stack and the
+    /// high resolution screen, for frontends that need a fixed size
+    pub fn max_state_length(&self) -> usize {
+        let stack = (STACK_SIZE - self.cpu.stack().len()) * 2;
+        let screen = HIRES_WIDTH * HIRES_HEIGHT - self.screen.pixels().len();
+        self.save_state().len() + stack + screen
+    }
+
+    /// Leaves the VM untouched if the state can't be restored
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state)?;
+        let mut cpu = CPU::load_state(&mut reader)?;
+        let mut memory = Memory::load_state(&mut reader, cpu.mode().memory_length())?;
+        let screen = Screen::load_state(&mut reader)?;
+        reader.finish()?;
+        cpu.adopt_random_source(&mut self.cpu);
+        memory.adopt_watchpoints(&mut self.memory);
+        *self = VM {
+            cpu,
+            memory,
+            screen,
+            #[cfg(feature = "std")]
+            trace: self.trace.take(),
+            #[cfg(feature = "std")]
+            profile: self.profile.take(),
+        };
+        Ok(())
+    }
+
+    /// Seed of the random source, picked at random unless set
+    pub fn seed(&self) -> u64 {
+        self.cpu.seed()
+    }
+
+    /// Makes RND reproducible
+    pub fn set_seed(&mut self, seed: u64) {
+        self.cpu.set_seed(seed)
     }
 
+    /// Replaces the xorshift generator behind RND, e.g. with a scripted one for tests
+    pub fn set_random_source(&mut self, source: Box<dyn RandomSource>) {
+        self.cpu.set_random_source(source)
+    }
+
+    /// Every pixel of the screen as 0RGB in the default palette,
+    /// `resolution()` wide and high
     pub fn get_current_frame(&self) -> Vec<u32> {
-        self.frame_buffer.clone()
+        self.frame(&Palette::DEFAULT)
+    }
+
+    /// Every pixel of the screen as 0RGB in `palette`
+    pub fn frame(&self, palette: &Palette) -> Vec<u32> {
+        self.screen
+            .pixels()
+            .iter()
+            .map(|pixel| palette.color(*pixel))
+            .collect()
     }
 
-    pub fn tick(&mut self, keys: &[Key]) {
-        self.cpu
-            .tick(&mut self.memory, &mut self.frame_buffer, keys)
+    /// Palette index of every pixel, one bit per plane
+    pub fn pixels(&self) -> &[u8] {
+        self.screen.pixels()
+    }
+
+    /// Width and height of the screen, which changes with `hires` and `lores`
+    pub fn resolution(&self) -> (usize, usize) {
+        (self.screen.width(), self.screen.height())
+    }
+
+    /// Whether the ROM ran EXIT
+    pub fn is_halted(&self) -> bool {
+        self.cpu.is_halted()
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    /// Lets debuggers change registers and PC between instructions
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Memory ranges whose accesses get reported by `take_watch_hit`
+    pub fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        self.memory.set_watchpoints(watchpoints)
+    }
+
+    /// First watched memory access since the last call
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.memory.take_hit()
+    }
+
+    /// The XO-CHIP audio pattern and pitch, played while `is_sound_playing`
+    pub fn audio(&self) -> &Audio {
+        self.cpu.audio()
+    }
+
+    /// Whether the sound timer is running
+    pub fn is_sound_playing(&self) -> bool {
+        self.cpu.is_sound_playing()
+    }
+
+    /// Logs every instruction executed from now on, or stops logging
+    #[cfg(feature = "std")]
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace
+    }
+
+    /// Starts counting executed instructions, or stops
+    #[cfg(feature = "std")]
+    pub fn set_profile(&mut self, profile: Option<Profile>) {
+        self.profile = profile
+    }
+
+    /// Counts gathered since `set_profile`
+    #[cfg(feature = "std")]
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Runs a single instruction with `keys` held down
+    pub fn step_instruction(&mut self, keys: &[Key]) -> Result<(), EmulatorError> {
+        #[cfg(feature = "std")]
+        {
+            if let (Some(profile), false) = (self.profile.as_mut(), self.cpu.is_halted()) {
+                let address = self.cpu.program_counter();
+                if let Ok((opcode, _)) = self.cpu.instruction_at(&self.memory, address) {
+                    profile.instruction(address, &opcode);
+                }
+            }
+            if let Some(trace) = self.trace.as_mut() {
+                return trace.step(&mut self.cpu, &mut self.memory, &mut self.screen, keys);
+            }
+        }
+        self.cpu.tick(&mut self.memory, &mut self.screen, keys)
+    }
+
+    /// Decrements DT and ST, should be called at 60Hz regardless of CPU speed
+    pub fn tick_timers(&mut self) {
+        #[cfg(feature = "std")]
+        if let Some(profile) = self.profile.as_mut() {
+            profile.frame();
+        }
+        self.cpu.tick_timers()
+    }
+
+    /// Runs one 60Hz frame worth of instructions followed by a timer tick
+    pub fn run_frame(
+        &mut self,
+        keys: &[Key],
+        instructions_per_frame: usize,
+    ) -> Result<(), EmulatorError> {
+        for _ in 0..instructions_per_frame {
+            if self.is_halted() {
+                break;
+            }
+            self.step_instruction(keys)?;
+        }
+        self.tick_timers();
+        Ok(())
     }
 }
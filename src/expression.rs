@@ -0,0 +1,263 @@
+use chip_8::engine::register::Register;
+use chip_8::engine::vm::VM;
+use std::fmt::{Display, Formatter};
+
+// Debugger conditions such as `V3 == 0x10 && I > 0x300`. Operands are V0 to VF,
+// I, PC, SP (the stack depth), DT, ST, decimal or 0x prefixed hexadecimal
+// numbers and `[address]` for a byte of memory. Comparisons and logic
+// operators give 1 or 0, and anything but 0 counts as true.
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+enum Node {
+    Number(u32),
+    Register(u8),
+    Index,
+    ProgramCounter,
+    StackPointer,
+    DelayTimer,
+    SoundTimer,
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Operator {
+    Or,
+    And,
+    BitOr,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+}
+
+impl Operator {
+    // Longest symbols first so that `||` is not read as two `|`
+    const SYMBOLS: [(&'static str, Operator); 12] = [
+        ("||", Operator::Or),
+        ("&&", Operator::And),
+        ("==", Operator::Equal),
+        ("!=", Operator::NotEqual),
+        ("<=", Operator::LessOrEqual),
+        (">=", Operator::GreaterOrEqual),
+        ("|", Operator::BitOr),
+        ("&", Operator::BitAnd),
+        ("<", Operator::Less),
+        (">", Operator::Greater),
+        ("+", Operator::Add),
+        ("-", Operator::Subtract),
+    ];
+
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::BitOr => 3,
+            Operator::BitAnd => 4,
+            Operator::Equal | Operator::NotEqual => 5,
+            Operator::Less
+            | Operator::LessOrEqual
+            | Operator::Greater
+            | Operator::GreaterOrEqual => 6,
+            Operator::Add | Operator::Subtract => 7,
+        }
+    }
+
+    fn apply(self, left: u32, right: u32) -> u32 {
+        match self {
+            Operator::Or => (left != 0 || right != 0) as u32,
+            Operator::And => (left != 0 && right != 0) as u32,
+            Operator::BitOr => left | right,
+            Operator::BitAnd => left & right,
+            Operator::Equal => (left == right) as u32,
+            Operator::NotEqual => (left != right) as u32,
+            Operator::Less => (left < right) as u32,
+            Operator::LessOrEqual => (left <= right) as u32,
+            Operator::Greater => (left > right) as u32,
+            Operator::GreaterOrEqual => (left >= right) as u32,
+            Operator::Add => left.wrapping_add(right),
+            Operator::Subtract => left.wrapping_sub(right),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Token {
+    Number(u32),
+    Name(String),
+    Operator(Operator),
+    Not,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let length = if c.is_ascii_alphanumeric() {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let word = &rest[..length];
+            if c.is_ascii_digit() {
+                let number = match word.strip_prefix("0x") {
+                    Some(digits) => u32::from_str_radix(digits, 16),
+                    None => word.parse(),
+                };
+                tokens.push(Token::Number(
+                    number.map_err(|_| format!("invalid number {}", word))?,
+                ));
+            } else {
+                tokens.push(Token::Name(word.to_ascii_uppercase()));
+            }
+            length
+        } else if let Some((symbol, operator)) = Operator::SYMBOLS
+            .iter()
+            .find(|(symbol, _)| rest.starts_with(symbol))
+        {
+            tokens.push(Token::Operator(*operator));
+            symbol.len()
+        } else {
+            tokens.push(match c {
+                '!' => Token::Not,
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '[' => Token::OpenBracket,
+                ']' => Token::CloseBracket,
+                _ => return Err(format!("unexpected character {}", c)),
+            });
+            1
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, description: &str) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(format!("expected {}", description)),
+        }
+    }
+
+    // Precedence climbing, every operator is left associative
+    fn binary(&mut self, minimum_precedence: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Operator(operator)) = self.tokens.get(self.position) {
+            let operator = *operator;
+            if operator.precedence() < minimum_precedence {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(operator.precedence() + 1)?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Node::Number(number)),
+            Some(Token::Not) => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let node = self.binary(0)?;
+                self.expect(Token::Close, ")")?;
+                Ok(node)
+            }
+            Some(Token::OpenBracket) => {
+                let node = self.binary(0)?;
+                self.expect(Token::CloseBracket, "]")?;
+                Ok(Node::Memory(Box::new(node)))
+            }
+            Some(Token::Name(name)) => match name.as_str() {
+                "I" => Ok(Node::Index),
+                "PC" => Ok(Node::ProgramCounter),
+                "SP" => Ok(Node::StackPointer),
+                "DT" => Ok(Node::DelayTimer),
+                "ST" => Ok(Node::SoundTimer),
+                _ => name
+                    .strip_prefix('V')
+                    .filter(|digit| digit.len() == 1)
+                    .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                    .map(Node::Register)
+                    .ok_or_else(|| format!("unknown name {}", name)),
+            },
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err(String::from("unexpected end of expression")),
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let root = parser.binary(0)?;
+        if let Some(token) = parser.next() {
+            return Err(format!("unexpected {:?}", token));
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn evaluate(&self, vm: &VM) -> u32 {
+        evaluate(&self.root, vm)
+    }
+
+    pub fn is_true(&self, vm: &VM) -> bool {
+        self.evaluate(vm) != 0
+    }
+}
+
+fn evaluate(node: &Node, vm: &VM) -> u32 {
+    let cpu = vm.cpu();
+    match node {
+        Node::Number(number) => *number,
+        Node::Register(id) => cpu.registers().read(&Register::new(*id as u16).unwrap()) as u32,
+        Node::Index => cpu.registers().i as u32,
+        Node::ProgramCounter => cpu.program_counter() as u32,
+        Node::StackPointer => cpu.stack().len() as u32,
+        Node::DelayTimer => cpu.registers().dt as u32,
+        Node::SoundTimer => cpu.registers().st as u32,
+        // Addresses outside of memory read as 0
+        Node::Memory(address) => vm.memory().peek(evaluate(address, vm) as u16).unwrap_or(0) as u32,
+        Node::Not(node) => (evaluate(node, vm) == 0) as u32,
+        Node::Binary(operator, left, right) => {
+            operator.apply(evaluate(left, vm), evaluate(right, vm))
+        }
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
@@ -0,0 +1,491 @@
+use chip_8::engine::error::EmulatorError;
+use chip_8::engine::key::Key;
+use chip_8::engine::memory::Watchpoint;
+use chip_8::engine::register::Register;
+use chip_8::engine::vm::VM;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::io::{BufReader, Read, Write};
+
+// Register numbers in `g`, `p` and `P` packets, in this order: V0 to VF, I,
+// PC, SP (the stack depth, read only), DT and ST. Values are big endian.
+const REGISTER_COUNT: usize = 21;
+const INDEX: usize = 16;
+const PROGRAM_COUNTER: usize = 17;
+const STACK_POINTER: usize = 18;
+const DELAY_TIMER: usize = 19;
+const SOUND_TIMER: usize = 20;
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+pub enum Packet {
+    Command(Vec<u8>),
+    // Ctrl-C from GDB while the target runs
+    Interrupt,
+    // Checksum mismatch, GDB sends it again on a NAK
+    Corrupt,
+    Closed,
+}
+
+// Splits the byte stream coming from GDB into packets until the connection
+// closes or `send` returns false
+pub fn read_packets<R: Read, F: FnMut(Packet) -> bool>(reader: R, mut send: F) {
+    let mut bytes = BufReader::new(reader).bytes().map_while(Result::ok);
+    while let Some(byte) = bytes.next() {
+        let packet = match byte {
+            0x03 => Packet::Interrupt,
+            b'$' => {
+                let mut data = vec![];
+                let mut sum: u8 = 0;
+                for byte in bytes.by_ref() {
+                    if byte == b'#' {
+                        break;
+                    }
+                    sum = sum.wrapping_add(byte);
+                    data.push(byte);
+                }
+                let checksum: Vec<u8> = bytes.by_ref().take(2).collect();
+                let expected = std::str::from_utf8(&checksum)
+                    .ok()
+                    .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+                if expected == Some(sum) {
+                    Packet::Command(unescape(&data))
+                } else {
+                    Packet::Corrupt
+                }
+            }
+            // Acknowledgements of our replies, and noise between packets
+            _ => continue,
+        };
+        if !send(packet) {
+            return;
+        }
+    }
+    send(Packet::Closed);
+}
+
+// Binary data escapes `#`, `$`, `}` and `*` as `}` followed by the byte XOR 0x20
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped = vec![];
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => unescaped.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            _ => unescaped.push(*byte),
+        }
+    }
+    unescaped
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+// Target side of the GDB remote serial protocol. Packets are read on another
+// thread and handed over with `handle`, replies are written straight back.
+pub struct GdbStub {
+    writer: Box<dyn Write + Send>,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(Watchpoint, WatchKind)>,
+    paused: bool,
+    acknowledge: bool,
+}
+
+impl GdbStub {
+    // GDB expects the target to be stopped when it connects
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            breakpoints: BTreeSet::new(),
+            watchpoints: vec![],
+            paused: true,
+            acknowledge: true,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Like `VM::run_frame`, but stops in the middle of the frame at
+    // breakpoints and watchpoints, in which case timers are not ticked
+    pub fn run_frame(
+        &mut self,
+        vm: &mut VM,
+        keys: &[Key],
+        instructions_per_frame: usize,
+    ) -> Result<(), EmulatorError> {
+        for _ in 0..instructions_per_frame {
+            if vm.is_halted() {
+                self.paused = true;
+                self.reply("W00").ok();
+                return Ok(());
+            }
+            if let Err(e) = vm.step_instruction(keys) {
+                self.stop_with_error(&e);
+                return Err(e);
+            }
+            if let Some(reply) = self.stop_reply(vm) {
+                self.paused = true;
+                self.reply(&reply).ok();
+                return Ok(());
+            }
+        }
+        vm.tick_timers();
+        Ok(())
+    }
+
+    // Stop reply for a breakpoint or watchpoint hit by the last instruction
+    fn stop_reply(&self, vm: &VM) -> Option<String> {
+        if let Some(hit) = vm.take_watch_hit() {
+            let covers = |kind: WatchKind| {
+                self.watchpoints.iter().any(|(watchpoint, watch_kind)| {
+                    *watch_kind == kind
+                        && (watchpoint.start..=watchpoint.end).contains(&hit.address)
+                })
+            };
+            let kind = if covers(WatchKind::Access) {
+                "awatch"
+            } else if hit.write {
+                "watch"
+            } else {
+                "rwatch"
+            };
+            return Some(format!("T{:02x}{}:{:x};", SIGTRAP, kind, hit.address));
+        }
+        if self.breakpoints.contains(&vm.cpu().program_counter()) {
+            return Some(format!("S{:02x}", SIGTRAP));
+        }
+        None
+    }
+
+    fn stop_with_error(&mut self, error: &EmulatorError) {
+        let signal = match error {
+            EmulatorError::UnknownOpcode { .. } => SIGILL,
+            _ => SIGSEGV,
+        };
+        self.paused = true;
+        self.reply(&format!("S{:02x}", signal)).ok();
+    }
+
+    pub fn handle(&mut self, vm: &mut VM, keys: &[Key], packet: Packet) -> std::io::Result<()> {
+        let data = match packet {
+            Packet::Command(data) => data,
+            Packet::Interrupt => {
+                if !self.paused {
+                    self.paused = true;
+                    self.reply(&format!("S{:02x}", SIGINT))?;
+                }
+                return Ok(());
+            }
+            Packet::Corrupt => {
+                self.writer.write_all(b"-")?;
+                return self.writer.flush();
+            }
+            // Let the ROM run on its own
+            Packet::Closed => {
+                self.detach(vm);
+                return Ok(());
+            }
+        };
+        if self.acknowledge {
+            self.writer.write_all(b"+")?;
+        }
+        let packet = String::from_utf8_lossy(&data).into_owned();
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..REGISTER_COUNT)
+                .map(|register| read_register(vm, register))
+                .collect(),
+            "G" => {
+                let mut rest = arguments;
+                for register in 0..REGISTER_COUNT {
+                    let width = read_register(vm, register).len();
+                    if rest.len() < width {
+                        break;
+                    }
+                    let (value, remaining) = rest.split_at(width);
+                    if register != STACK_POINTER {
+                        write_register(vm, register, value);
+                    }
+                    rest = remaining;
+                }
+                String::from("OK")
+            }
+            "p" => match usize::from_str_radix(arguments, 16) {
+                Ok(register) if register < REGISTER_COUNT => read_register(vm, register),
+                _ => String::from("E01"),
+            },
+            "P" => match arguments.split_once('=').and_then(|(register, value)| {
+                Some((usize::from_str_radix(register, 16).ok()?, value))
+            }) {
+                Some((register, value))
+                    if register < REGISTER_COUNT && register != STACK_POINTER =>
+                {
+                    if write_register(vm, register, value) {
+                        String::from("OK")
+                    } else {
+                        String::from("E01")
+                    }
+                }
+                _ => String::from("E01"),
+            },
+            "m" => parse_range(arguments)
+                .and_then(|(address, length)| {
+                    (0..length)
+                        .map(|offset| {
+                            let address = u16::try_from(address.checked_add(offset)?).ok()?;
+                            vm.memory().peek(address).ok()
+                        })
+                        .map(|byte| byte.map(|byte| format!("{:02x}", byte)))
+                        .collect::<Option<String>>()
+                })
+                .unwrap_or_else(|| String::from("E01")),
+            "M" => {
+                let written = arguments.split_once(':').and_then(|(range, data)| {
+                    let (address, length) = parse_range(range)?;
+                    let bytes = decode_hex(data)?;
+                    if bytes.len() != length {
+                        return None;
+                    }
+                    write_memory(vm, address, &bytes)
+                });
+                ok_or_error(written)
+            }
+            "X" => {
+                // The data after `:` is binary, unlike the rest of the packet
+                let colon = data.iter().position(|byte| *byte == b':');
+                let written = colon.and_then(|colon| {
+                    let range = std::str::from_utf8(&data[1..colon]).ok()?;
+                    let (address, length) = parse_range(range)?;
+                    let bytes = &data[colon + 1..];
+                    if bytes.len() != length {
+                        return None;
+                    }
+                    write_memory(vm, address, bytes)
+                });
+                ok_or_error(written)
+            }
+            "c" => {
+                if let Some(address) = parse_address(arguments) {
+                    vm.cpu_mut().set_program_counter(address);
+                }
+                self.paused = false;
+                return self.writer.flush();
+            }
+            "s" => {
+                if let Some(address) = parse_address(arguments) {
+                    vm.cpu_mut().set_program_counter(address);
+                }
+                if vm.is_halted() {
+                    String::from("W00")
+                } else {
+                    match vm.step_instruction(keys) {
+                        Ok(()) => self
+                            .stop_reply(vm)
+                            .unwrap_or_else(|| format!("S{:02x}", SIGTRAP)),
+                        Err(e) => {
+                            self.stop_with_error(&e);
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            "Z" | "z" => ok_or_error(self.set_breakpoint(vm, command == "Z", arguments)),
+            "D" => {
+                self.detach(vm);
+                String::from("OK")
+            }
+            "k" => {
+                self.detach(vm);
+                return Ok(());
+            }
+            "H" => String::from("OK"),
+            "q" | "Q" => self.query(&packet),
+            // Anything else is unsupported, which GDB is told with an empty reply
+            _ => String::new(),
+        };
+        self.reply(&reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return String::from("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+");
+        }
+        if packet == "QStartNoAckMode" {
+            // This reply is still acknowledged
+            self.acknowledge = false;
+            return String::from("OK");
+        }
+        if packet == "qAttached" {
+            return String::from("1");
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let description = target_description();
+            return match parse_range(range) {
+                Some((offset, length)) if offset <= description.len() => {
+                    let end = (offset + length).min(description.len());
+                    let marker = if end == description.len() { 'l' } else { 'm' };
+                    format!("{}{}", marker, &description[offset..end])
+                }
+                _ => String::from("E01"),
+            };
+        }
+        String::new()
+    }
+
+    // `Z<type>,<address>,<kind>`: types 0 and 1 are breakpoints, 2 to 4 are
+    // write, read and access watchpoints over `kind` bytes
+    fn set_breakpoint(&mut self, vm: &mut VM, insert: bool, arguments: &str) -> Option<()> {
+        let mut fields = arguments.split(',');
+        let kind = fields.next()?;
+        let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let length = u16::from_str_radix(fields.next()?.split(';').next()?, 16).ok()?;
+        let watch_kind = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                return Some(());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return None,
+        };
+        let watchpoint = Watchpoint {
+            start: address,
+            end: address.saturating_add(length.max(1) - 1),
+            reads: watch_kind != WatchKind::Write,
+            writes: watch_kind != WatchKind::Read,
+        };
+        if insert {
+            self.watchpoints.push((watchpoint, watch_kind));
+        } else {
+            self.watchpoints
+                .retain(|watch| *watch != (watchpoint, watch_kind));
+        }
+        vm.set_watchpoints(self.watchpoints.iter().map(|(watch, _)| *watch).collect());
+        Some(())
+    }
+
+    fn detach(&mut self, vm: &mut VM) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        vm.set_watchpoints(vec![]);
+        self.paused = false;
+    }
+
+    fn reply(&mut self, data: &str) -> std::io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.writer, "${}#{:02x}", data, checksum)?;
+        self.writer.flush()
+    }
+}
+
+fn read_register(vm: &VM, register: usize) -> String {
+    let cpu = vm.cpu();
+    match register {
+        0..=15 => format!(
+            "{:02x}",
+            cpu.registers()
+                .read(&Register::new(register as u16).unwrap())
+        ),
+        INDEX => format!("{:04x}", cpu.registers().i),
+        PROGRAM_COUNTER => format!("{:04x}", cpu.program_counter()),
+        STACK_POINTER => format!("{:02x}", cpu.stack().len()),
+        DELAY_TIMER => format!("{:02x}", cpu.registers().dt),
+        _ => format!("{:02x}", cpu.registers().st),
+    }
+}
+
+fn write_register(vm: &mut VM, register: usize, value: &str) -> bool {
+    let value = match u16::from_str_radix(value, 16) {
+        Ok(value) => value,
+        Err(_) => return false,
+    };
+    let cpu = vm.cpu_mut();
+    match register {
+        0..=15 => cpu
+            .registers_mut()
+            .write(&Register::new(register as u16).unwrap(), value as u8),
+        INDEX => cpu.registers_mut().i = value,
+        PROGRAM_COUNTER => cpu.set_program_counter(value),
+        DELAY_TIMER => cpu.registers_mut().dt = value as u8,
+        SOUND_TIMER => cpu.registers_mut().st = value as u8,
+        _ => return false,
+    }
+    true
+}
+
+fn write_memory(vm: &mut VM, address: usize, bytes: &[u8]) -> Option<()> {
+    for (offset, byte) in bytes.iter().enumerate() {
+        let address = u16::try_from(address.checked_add(offset)?).ok()?;
+        vm.memory_mut().poke(address, *byte).ok()?;
+    }
+    Some(())
+}
+
+// `<address>,<length>` in hexadecimal
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let (address, length) = range.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn parse_address(address: &str) -> Option<u16> {
+    u16::from_str_radix(address, 16).ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn ok_or_error(result: Option<()>) -> String {
+    match result {
+        Some(()) => String::from("OK"),
+        None => String::from("E01"),
+    }
+}
+
+// Register layout for GDB, which has no CHIP-8 architecture of its own
+fn target_description() -> String {
+    let mut registers: Vec<String> = (0..16)
+        .map(|i| format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", i))
+        .collect();
+    registers.push(String::from(
+        "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>",
+    ));
+    registers.push(String::from(
+        "<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>",
+    ));
+    for name in ["sp", "dt", "st"].iter() {
+        registers.push(format!(
+            "<reg name=\"{}\" bitsize=\"8\" type=\"uint8\"/>",
+            name
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8.core\">{}</feature></target>",
+        registers.concat()
+    )
+}
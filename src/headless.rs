@@ -0,0 +1,122 @@
+use chip_8::engine::error::EmulatorError;
+use chip_8::engine::key::Key;
+use chip_8::engine::palette::Palette;
+use chip_8::engine::vm::VM;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    Pbm,
+    Ppm,
+    Ascii,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "pbm" => Some(Format::Pbm),
+            "ppm" => Some(Format::Ppm),
+            "ascii" => Some(Format::Ascii),
+            _ => None,
+        }
+    }
+}
+
+// Keys to hold from a frame onwards, one `<frame> <keys>` pair per line with
+// keys as hex digits (`-` for none), e.g. `120 5a` holds 5 and A from frame
+// 120 until the next line. Lines starting with `#` are ignored.
+pub struct InputScript {
+    events: Vec<(usize, Vec<Key>)>,
+}
+
+impl InputScript {
+    pub fn empty() -> Self {
+        Self { events: vec![] }
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut events: Vec<(usize, Vec<Key>)> = vec![];
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+            let mut fields = line.split_whitespace();
+            let frame = fields
+                .next()
+                .and_then(|frame| frame.parse::<usize>().ok())
+                .ok_or_else(|| error("expected a frame number"))?;
+            let keys = match fields.next() {
+                None | Some("-") => vec![],
+                Some(keys) => keys
+                    .chars()
+                    .map(|c| {
+                        c.to_digit(16)
+                            .and_then(|value| Key::from_value(value as u8))
+                            .ok_or_else(|| error("keys must be hex digits"))
+                    })
+                    .collect::<Result<Vec<Key>, String>>()?,
+            };
+            if events.last().is_some_and(|(last, _)| *last > frame) {
+                return Err(error("frames must be in increasing order"));
+            }
+            events.push((frame, keys));
+        }
+        Ok(Self { events })
+    }
+
+    pub fn keys_at(&self, frame: usize) -> &[Key] {
+        self.events
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= frame)
+            .map_or(&[], |(_, keys)| keys.as_slice())
+    }
+}
+
+// Runs until `frames` frames went by or the ROM exits, `input` gives the keys
+// held and the instructions to run for every frame
+pub fn run<F: FnMut(usize) -> (Vec<Key>, usize)>(
+    vm: &mut VM,
+    frames: usize,
+    mut input: F,
+) -> Result<(), EmulatorError> {
+    for frame in 0..frames {
+        if vm.is_halted() {
+            break;
+        }
+        let (keys, instructions_per_frame) = input(frame);
+        vm.run_frame(&keys, instructions_per_frame)?;
+    }
+    Ok(())
+}
+
+// PPM is the only format in colour, the others show pixel values
+pub fn render(vm: &VM, format: Format, palette: &Palette) -> String {
+    let (width, height) = vm.resolution();
+    let mut output = String::new();
+    match format {
+        Format::Pbm => output.push_str(&format!("P1\n{} {}\n", width, height)),
+        Format::Ppm => output.push_str(&format!("P3\n{} {}\n255\n", width, height)),
+        Format::Ascii => {}
+    }
+    for row in vm.pixels().chunks(width) {
+        let line: Vec<String> = row
+            .iter()
+            .map(|pixel| match format {
+                Format::Pbm => String::from(if *pixel != 0 { "1" } else { "0" }),
+                Format::Ppm => {
+                    let color = palette.color(*pixel);
+                    format!("{} {} {}", color >> 16, (color >> 8) & 0xff, color & 0xff)
+                }
+                Format::Ascii => String::from(['.', '#', '+', '@'][*pixel as usize]),
+            })
+            .collect();
+        match format {
+            Format::Pbm | Format::Ppm => output.push_str(&line.join(" ")),
+            Format::Ascii => output.push_str(&line.concat()),
+        }
+        output.push('\n');
+    }
+    output
+}
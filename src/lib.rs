@@ -0,0 +1,47 @@
+//! A CHIP-8, SUPER-CHIP and XO-CHIP interpreter.
+//!
+//! [`VM`] holds the whole machine. Frontends load a ROM, then call
+//! [`VM::run_frame`] 60 times per second with the keys held down and draw
+//! [`VM::get_current_frame`]:
+//!
+//! ```
+//! use chip_8::{Key, Mode, VM};
+//!
+//! // LD V0, K then JP 0x200: waits for a key, forever
+//! let rom = [0xf0, 0x0a, 0x12, 0x00];
+//! let mode = Mode::Chip8;
+//! let mut vm = VM::new(&rom, mode, mode.default_quirks()).unwrap();
+//! vm.run_frame(&[Key::Key5], 10).unwrap();
+//!
+//! let (width, height) = vm.resolution();
+//! assert_eq!(vm.get_current_frame().len(), width * height);
+//!
+//! let state = vm.save_state();
+//! vm.load_state(&state).unwrap();
+//! ```
+//!
+//! The screen holds a value per pixel rather than a colour, [`VM::pixels`]
+//! gives them as they are and [`VM::frame`] maps them through a [`Palette`].
+//!
+//! The CPU, memory and decoded [`Opcode`]s are reachable through
+//! [`VM::cpu`] and [`VM::memory`] for debuggers and other tools.
+//!
+//! Without the `std` feature the crate is `no_std` and only needs `alloc`.
+//! Tracing, profiling and random seeds are left out, RND starts from the
+//! same seed in every VM unless [`VM::set_seed`] is called.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod engine;
+
+pub use engine::cpu::CPU;
+pub use engine::error::EmulatorError;
+pub use engine::key::Key;
+pub use engine::memory::Memory;
+pub use engine::mode::Mode;
+pub use engine::opcode::Opcode;
+pub use engine::palette::Palette;
+pub use engine::quirks::Quirks;
+pub use engine::vm::VM;
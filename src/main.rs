@@ -1,75 +1,918 @@
-extern crate minifb;
+mod assembler;
+mod debugger;
+mod disassembler;
+mod expression;
+mod gdb;
+mod headless;
+mod movie;
+mod octo;
+mod rewind;
+mod terminal;
 
-mod engine;
-
-use engine::key::Key;
-use engine::vm::{HEIGHT, VM, WIDTH};
+use chip_8::engine::key::Key;
+use chip_8::engine::mode::Mode;
+use chip_8::engine::palette::Palette;
+use chip_8::engine::profile::Profile;
+use chip_8::engine::quirks::Quirks;
+use chip_8::engine::trace::{Trace, TraceFilter};
+use chip_8::engine::vm::{HIRES_HEIGHT, HIRES_WIDTH, VM};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use crossterm::event::{KeyCode, KeyEventKind, KeyModifiers};
+use debugger::Debugger;
+use disassembler::Syntax;
+use gdb::{GdbStub, Packet};
+use headless::{Format, InputScript};
 use minifb::{Scale, Window, WindowOptions};
+use movie::Movie;
+use rewind::Rewind;
 use std::collections::HashSet;
-use std::env;
+use std::io::{BufRead, Read, Write};
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
+use terminal::{Style, Terminal};
+
+const FRAME_DURATION: Duration = Duration::from_micros(16600);
+const FRAMES_PER_SECOND: usize = 60;
+const MAX_INSTRUCTIONS_PER_FRAME: usize = 1000;
+const REWIND_FRAMES: usize = 10 * FRAMES_PER_SECOND;
+const HEADLESS_FRAMES: usize = 10 * FRAMES_PER_SECOND;
+
+// Sent from the window thread, or the debugger prompt, to the emulation thread
+enum Command {
+    SetSpeed(usize),
+    SaveState(usize),
+    LoadState(usize),
+    Rewind(bool),
+    Pause(bool),
+    Debug(String),
+    Gdb(Packet),
+    Quit,
+}
+
+const STATE_SLOT_KEYS: [minifb::Key; 8] = [
+    minifb::Key::F1,
+    minifb::Key::F2,
+    minifb::Key::F3,
+    minifb::Key::F4,
+    minifb::Key::F5,
+    minifb::Key::F6,
+    minifb::Key::F7,
+    minifb::Key::F8,
+];
+
+fn is_seed(value: String) -> Result<(), String> {
+    value
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| String::from("must be a number"))
+}
+
+fn is_speed(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(speed) if speed > 0 => Ok(()),
+        _ => Err(String::from("must be a positive number")),
+    }
+}
+
+fn is_gdb_port(value: String) -> Result<(), String> {
+    if value == "-" || value.parse::<u16>().is_ok() {
+        return Ok(());
+    }
+    Err(String::from("must be a port number or -"))
+}
+
+// `200-2ff`, or a single address like `2a4`
+fn parse_range(value: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = value.split_once('-').unwrap_or((value, value));
+    let start = u16::from_str_radix(start.trim(), 16).ok()?;
+    let end = u16::from_str_radix(end.trim(), 16).ok()?;
+    if start > end {
+        return None;
+    }
+    Some(start..=end)
+}
+
+fn is_range(value: String) -> Result<(), String> {
+    match parse_range(&value) {
+        Some(_) => Ok(()),
+        None => Err(String::from("must be a hex address or range like 200-2ff")),
+    }
+}
+
+// A preset, or 2 or 4 comma separated hex colours: background and foreground,
+// then the second plane and the overlap for XO-CHIP
+fn parse_palette(value: &str) -> Option<Palette> {
+    if let Some(palette) = Palette::from_name(value) {
+        return Some(palette);
+    }
+    let colors = value
+        .split(',')
+        .map(|color| u32::from_str_radix(color.trim().trim_start_matches('#'), 16).ok())
+        .collect::<Option<Vec<u32>>>()?;
+    if colors.iter().any(|color| *color > 0xffffff) {
+        return None;
+    }
+    match colors[..] {
+        [background, foreground] => Some(Palette::two_tone(background, foreground)),
+        [background, first, second, both] => Some(Palette {
+            colors: [background, first, second, both],
+        }),
+        _ => None,
+    }
+}
+
+fn is_palette(value: String) -> Result<(), String> {
+    match parse_palette(&value) {
+        Some(_) => Ok(()),
+        None => Err(String::from(
+            "must be default, amber, green, lcd, octo or 2 or 4 hex colours like 000000,ffb000",
+        )),
+    }
+}
+
+fn faster(instructions_per_frame: usize) -> usize {
+    let step = (instructions_per_frame / 10).max(1);
+    (instructions_per_frame + step).min(MAX_INSTRUCTIONS_PER_FRAME)
+}
+
+fn slower(instructions_per_frame: usize) -> usize {
+    let step = (instructions_per_frame / 10).max(1);
+    instructions_per_frame.saturating_sub(step).max(1)
+}
+
+fn exit_with_error<E: std::fmt::Display>(context: &str, error: E) -> ! {
+    eprintln!("{}: {}", context, error);
+    std::process::exit(1)
+}
+
+// The report goes to stderr with -
+fn write_profile(vm: &VM, path: &str) {
+    if let Some(profile) = vm.profile() {
+        let report = profile.report(vm.cpu(), vm.memory());
+        if path == "-" {
+            eprint!("{}", report);
+        } else if let Err(e) = std::fs::write(path, report) {
+            eprintln!("Unable to write profile: {}", e);
+        }
+    }
+}
+
+// The keypad is laid out on the left of a QWERTY keyboard:
+//
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D      Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+//
+// Windows report keys, terminals the characters they type
+const KEYPAD: [(Key, minifb::Key, char); 16] = [
+    (Key::Key1, minifb::Key::Key1, '1'),
+    (Key::Key2, minifb::Key::Key2, '2'),
+    (Key::Key3, minifb::Key::Key3, '3'),
+    (Key::KeyC, minifb::Key::Key4, '4'),
+    (Key::Key4, minifb::Key::Q, 'q'),
+    (Key::Key5, minifb::Key::W, 'w'),
+    (Key::Key6, minifb::Key::E, 'e'),
+    (Key::KeyD, minifb::Key::R, 'r'),
+    (Key::Key7, minifb::Key::A, 'a'),
+    (Key::Key8, minifb::Key::S, 's'),
+    (Key::Key9, minifb::Key::D, 'd'),
+    (Key::KeyE, minifb::Key::F, 'f'),
+    (Key::KeyA, minifb::Key::Z, 'z'),
+    (Key::Key0, minifb::Key::X, 'x'),
+    (Key::KeyB, minifb::Key::C, 'c'),
+    (Key::KeyF, minifb::Key::V, 'v'),
+];
+
+fn key(key: minifb::Key) -> Option<Key> {
+    KEYPAD
+        .iter()
+        .find(|(_, window_key, _)| *window_key == key)
+        .map(|(key, _, _)| *key)
+}
+
+fn terminal_key(code: KeyCode) -> Option<Key> {
+    KEYPAD
+        .iter()
+        .find(|(_, _, character)| code == KeyCode::Char(*character))
+        .map(|(key, _, _)| *key)
+}
+
+fn title(instructions_per_frame: usize, paused: bool) -> String {
+    format!(
+        "CHIP-8 ({} instructions/frame, {}Hz){}",
+        instructions_per_frame,
+        instructions_per_frame * FRAMES_PER_SECOND,
+        if paused { " - paused" } else { "" }
+    )
+}
+
+fn mode_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("mode")
+        .long("mode")
+        .takes_value(true)
+        .possible_values(&["chip8", "schip", "xochip"])
+        .default_value("chip8")
+        .help("Instruction set to run the ROM with")
+}
+
+// Octo sources are compiled, anything else is loaded as it is
+fn read_rom(path: &Path) -> Vec<u8> {
+    if path.extension().is_some_and(|extension| extension == "8o") {
+        let source = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| exit_with_error("Unable to read ROM", e));
+        octo::compile(&source).unwrap_or_else(|e| exit_with_error("Unable to compile ROM", e))
+    } else {
+        std::fs::read(path).unwrap_or_else(|e| exit_with_error("Unable to read ROM", e))
+    }
+}
+
+fn disassemble(matches: &ArgMatches) {
+    let rom = read_rom(Path::new(matches.value_of("ROM").unwrap()));
+    let mode = Mode::from_name(matches.value_of("mode").unwrap()).unwrap();
+    let syntax = if matches.is_present("octo") {
+        Syntax::Octo
+    } else {
+        Syntax::Mnemonic
+    };
+    let listing = disassembler::disassemble(&rom, mode, syntax);
+    match matches.value_of("output") {
+        Some(path) => std::fs::write(path, listing)
+            .unwrap_or_else(|e| exit_with_error("Unable to write output", e)),
+        None => print!("{}", listing),
+    }
+}
+
+fn assemble(matches: &ArgMatches) {
+    let source = Path::new(matches.value_of("SOURCE").unwrap());
+    let rom = if source
+        .extension()
+        .is_some_and(|extension| extension == "8o")
+    {
+        read_rom(source)
+    } else {
+        assembler::assemble(source).unwrap_or_else(|e| exit_with_error("Unable to assemble", e))
+    };
+    let output = match matches.value_of("output") {
+        Some(path) => PathBuf::from(path),
+        None => source.with_extension("ch8"),
+    };
+    std::fs::write(&output, rom).unwrap_or_else(|e| exit_with_error("Unable to write output", e));
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!(String::from("Usage: \"chip-8 <ROM>\""));
+    let matches = App::new("chip-8")
+        .about("Yet another CHIP-8 emulator")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(Arg::with_name("ROM").required(true).index(1).help("ROM to run, .8o files are compiled as Octo source"))
+        .arg(mode_arg())
+        .arg(
+            Arg::with_name("quirks")
+                .long("quirks")
+                .takes_value(true)
+                .possible_values(&["vip", "chip48", "schip", "xochip"])
+                .help("Interpreter whose ambiguous instruction behaviour to follow [default: depends on mode]"),
+        )
+        .arg(
+            Arg::with_name("speed")
+                .long("speed")
+                .takes_value(true)
+                .validator(is_speed)
+                .default_value("10")
+                .help("Instructions executed per frame, adjust at runtime with - and ="),
+        )
+        .arg(
+            Arg::with_name("hz")
+                .long("hz")
+                .takes_value(true)
+                .validator(is_speed)
+                .conflicts_with("speed")
+                .help("Instructions executed per second, instead of --speed"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .validator(is_seed)
+                .help("Seed for RND, to make runs reproducible [default: random]"),
+        )
+        .arg(
+            Arg::with_name("palette")
+                .long("palette")
+                .takes_value(true)
+                .validator(is_palette)
+                .default_value("default")
+                .help("Colours to draw with: default, amber, green, lcd, octo, or hex colours like 000000,ffb000"),
+        )
+        .arg(
+            Arg::with_name("terminal")
+                .long("terminal")
+                .conflicts_with_all(&["debug", "gdb", "headless"])
+                .help("Play in the terminal instead of a window, e.g. over SSH"),
+        )
+        .arg(
+            Arg::with_name("braille")
+                .long("braille")
+                .requires("terminal")
+                .help("Draw with braille characters, 2x4 pixels each, instead of coloured half blocks"),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .conflicts_with_all(&["record", "play", "headless"])
+                .help("Start paused with a debugger prompt on the terminal"),
+        )
+        .arg(
+            Arg::with_name("gdb")
+                .long("gdb")
+                .takes_value(true)
+                .value_name("PORT")
+                .validator(is_gdb_port)
+                .conflicts_with_all(&["debug", "record", "play", "headless"])
+                .help("Wait for GDB to connect on a TCP port, or on stdin and stdout with -"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Log every executed instruction to a file, or to stderr with -"),
+        )
+        .arg(
+            Arg::with_name("trace-range")
+                .long("trace-range")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("RANGE")
+                .validator(is_range)
+                .requires("trace")
+                .help("Only log instructions at these hex addresses, e.g. 200-2ff"),
+        )
+        .arg(
+            Arg::with_name("trace-opcode")
+                .long("trace-opcode")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME")
+                .requires("trace")
+                .help("Only log instructions of these kinds, e.g. DRW or CALL"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Count executed instructions and write a report on exit, to stderr with -"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .conflicts_with_all(&["play", "headless"])
+                .help("Record the keys of every frame to a movie file, written on exit"),
+        )
+        .arg(
+            Arg::with_name("play")
+                .long("play")
+                .takes_value(true)
+                .help("Replay a movie file, using the mode, quirks and seed it was recorded with"),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Run without a window and print the last frame"),
+        )
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .takes_value(true)
+                .validator(is_speed)
+                .requires("headless")
+                .help("Frames to run in headless mode [default: 600, or the length of the movie with --play]"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .long("input")
+                .takes_value(true)
+                .requires("headless")
+                .help("Key script for headless mode, lines of `<frame> <hex keys held>`"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .requires("headless")
+                .help("File to write the last frame to instead of stdout"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["pbm", "ppm", "ascii"])
+                .requires("headless")
+                .help("Format of the last frame, ppm in the --palette colours [default: from the output's extension, ascii otherwise]"),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Disassembles a ROM, telling code from data by following jumps and calls")
+                .arg(Arg::with_name("ROM").required(true).index(1))
+                .arg(mode_arg())
+                .arg(
+                    Arg::with_name("octo")
+                        .long("octo")
+                        .help("Print Octo source instead of mnemonics"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .takes_value(true)
+                        .help("File to write the listing to [default: stdout]"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("asm")
+                .about("Assembles mnemonics, as printed by disasm, or compiles Octo source into a ROM")
+                .arg(Arg::with_name("SOURCE").required(true).index(1))
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .takes_value(true)
+                        .help("File to write the ROM to [default: SOURCE with a .ch8 extension]"),
+                ),
+        )
+        .get_matches();
+
+    if let Some(matches) = matches.subcommand_matches("disasm") {
+        disassemble(matches);
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("asm") {
+        assemble(matches);
+        return;
     }
-    let rom = args[1].clone();
 
-    let mut options = WindowOptions::default();
-    options.scale = Scale::X16;
-    options.resize = true;
-    let mut window = Window::new("CHIP-8", WIDTH, HEIGHT, options).unwrap_or_else(|e| {
-        panic!("{}", e);
+    let rom_path = matches.value_of("ROM").unwrap().to_string();
+    let rom = read_rom(Path::new(&rom_path));
+    let playback = matches.value_of("play").map(|path| {
+        let movie = std::fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| Movie::from_bytes(&bytes))
+            .unwrap_or_else(|e| exit_with_error("Unable to read movie", e));
+        movie
+            .check_rom(&rom)
+            .unwrap_or_else(|e| exit_with_error("Unable to play movie", e));
+        movie
     });
+    let mode = match &playback {
+        Some(movie) => movie.mode,
+        None => Mode::from_name(matches.value_of("mode").unwrap()).unwrap(),
+    };
+    let quirks = match &playback {
+        Some(movie) => movie.quirks,
+        None => matches
+            .value_of("quirks")
+            .and_then(Quirks::from_name)
+            .unwrap_or_else(|| mode.default_quirks()),
+    };
+    let mut instructions_per_frame = match matches.value_of("hz") {
+        Some(hz) => (hz.parse::<usize>().unwrap() / FRAMES_PER_SECOND).max(1),
+        None => matches.value_of("speed").unwrap().parse::<usize>().unwrap(),
+    }
+    .min(MAX_INSTRUCTIONS_PER_FRAME);
+    let palette = parse_palette(matches.value_of("palette").unwrap()).unwrap();
+    let seed = matches
+        .value_of("seed")
+        .map(|seed| seed.parse::<u64>().unwrap());
+
+    let mut vm =
+        VM::new(&rom, mode, quirks).unwrap_or_else(|e| exit_with_error("Unable to load ROM", e));
+    match (&playback, seed) {
+        (Some(movie), _) => vm.set_seed(movie.seed),
+        (None, Some(seed)) => vm.set_seed(seed),
+        (None, None) => {}
+    }
+    if let Some(path) = matches.value_of("trace") {
+        let writer: Box<dyn Write + Send> = if path == "-" {
+            Box::new(std::io::stderr())
+        } else {
+            let file = std::fs::File::create(path)
+                .unwrap_or_else(|e| exit_with_error("Unable to create trace", e));
+            Box::new(std::io::BufWriter::new(file))
+        };
+        let filter = TraceFilter {
+            ranges: matches
+                .values_of("trace-range")
+                .map_or(vec![], |ranges| ranges.filter_map(parse_range).collect()),
+            opcodes: matches
+                .values_of("trace-opcode")
+                .map_or(vec![], |names| names.map(str::to_string).collect()),
+        };
+        vm.set_trace(Some(Trace::new(writer, filter)));
+    }
+    if matches.is_present("profile") {
+        vm.set_profile(Some(Profile::new()));
+    }
+    let profile_path = matches.value_of("profile").map(str::to_string);
+    let mut recording = matches
+        .value_of("record")
+        .map(|path| (path.to_string(), Movie::new(&rom, vm.seed(), mode, quirks)));
 
-    // Limit to max ~60 fps update rate
-    window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+    if matches.is_present("headless") {
+        // A default value would trip `requires("headless")` in window mode
+        let frames = match (matches.value_of("frames"), &playback) {
+            (Some(frames), _) => frames.parse::<usize>().unwrap(),
+            (None, Some(movie)) => movie.len(),
+            (None, None) => HEADLESS_FRAMES,
+        };
+        let output = matches.value_of("output");
+        let format = match matches.value_of("format") {
+            Some(format) => Format::from_name(format).unwrap(),
+            None if output.is_some_and(|path| path.ends_with(".pbm")) => Format::Pbm,
+            None if output.is_some_and(|path| path.ends_with(".ppm")) => Format::Ppm,
+            None => Format::Ascii,
+        };
+        let script = match matches.value_of("input") {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|source| InputScript::parse(&source))
+                .unwrap_or_else(|e| exit_with_error("Unable to read input script", e)),
+            None => InputScript::empty(),
+        };
+        let result = headless::run(&mut vm, frames, |frame| {
+            playback
+                .as_ref()
+                .and_then(|movie| movie.frame(frame))
+                .unwrap_or_else(|| (script.keys_at(frame).to_vec(), instructions_per_frame))
+        });
+        if let Some(path) = &profile_path {
+            write_profile(&vm, path);
+        }
+        if let Err(e) = result {
+            exit_with_error("Emulation stopped", e);
+        }
+        let frame = headless::render(&vm, format, &palette);
+        match output {
+            Some(path) => std::fs::write(path, frame)
+                .unwrap_or_else(|e| exit_with_error("Unable to write output", e)),
+            None => print!("{}", frame),
+        }
+        return;
+    }
 
-    let (tx_buf, rx_buf) = std::sync::mpsc::channel::<Vec<u32>>();
+    // Frames are sent as pixel values, coloured by the frontend, along with their resolution,
+    // which SUPER-CHIP ROMs can switch at any time
+    let (tx_buf, rx_buf) = std::sync::mpsc::channel::<(Vec<u8>, usize, usize)>();
     // We have to send keys pressed and keys released because the keys aren't sent every iteration
     let (tx_key_pressed, rx_key_pressed) = std::sync::mpsc::channel::<Vec<Key>>();
     let (tx_key_released, rx_key_released) = std::sync::mpsc::channel::<Vec<Key>>();
+    let (tx_command, rx_command) = std::sync::mpsc::channel::<Command>();
+
+    let mut debugger = if matches.is_present("debug") {
+        let tx_command = tx_command.clone();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+                if tx_command.send(Command::Debug(line)).is_err() {
+                    break;
+                }
+            }
+        });
+        let debugger = Debugger::new();
+        println!("Type help for a list of debugger commands");
+        debugger.prompt(&vm);
+        Some(debugger)
+    } else {
+        None
+    };
 
-    std::thread::spawn(move || {
-        let rom = std::fs::read(rom).unwrap();
-        let mut vm = VM::new(&rom);
+    let mut gdb = matches.value_of("gdb").map(|port| {
+        let (reader, writer): (Box<dyn Read + Send>, Box<dyn Write + Send>) = if port == "-" {
+            (Box::new(std::io::stdin()), Box::new(std::io::stdout()))
+        } else {
+            let listener = TcpListener::bind(("127.0.0.1", port.parse::<u16>().unwrap()))
+                .unwrap_or_else(|e| exit_with_error("Unable to listen for GDB", e));
+            eprintln!("Waiting for GDB on port {}", port);
+            let (stream, _) = listener
+                .accept()
+                .unwrap_or_else(|e| exit_with_error("Unable to accept GDB", e));
+            let writer = stream
+                .try_clone()
+                .unwrap_or_else(|e| exit_with_error("Unable to accept GDB", e));
+            (Box::new(stream), Box::new(writer))
+        };
+        let tx_command = tx_command.clone();
+        std::thread::spawn(move || {
+            gdb::read_packets(reader, |packet| {
+                tx_command.send(Command::Gdb(packet)).is_ok()
+            })
+        });
+        GdbStub::new(writer)
+    });
 
+    let emulation = std::thread::spawn(move || {
+        let state_path = |slot: usize| format!("{}.state{}", rom_path, slot);
+        // Frames run since the start, going back while rewinding
+        let mut frame = 0;
         let mut last_instant = Instant::now();
         let mut keys: HashSet<Key> = HashSet::new();
+        // Cleared when the ROM fails, loading a state resumes emulation
+        let mut running = true;
+        let mut rewind = Rewind::new(REWIND_FRAMES);
+        let mut rewinding = false;
+        let mut paused = false;
+        rewind.push(vm.save_state());
         loop {
-            std::thread::sleep(Duration::from_micros(1660) - last_instant.elapsed());
+            for command in rx_command.try_iter() {
+                match command {
+                    Command::SetSpeed(speed) => instructions_per_frame = speed,
+                    Command::SaveState(slot) => {
+                        match std::fs::write(state_path(slot), vm.save_state()) {
+                            Ok(()) => eprintln!("Saved state {}", slot),
+                            Err(e) => eprintln!("Unable to save state {}: {}", slot, e),
+                        }
+                    }
+                    Command::LoadState(_) if recording.is_some() || playback.is_some() => {
+                        eprintln!("Loading states is disabled while recording or playing a movie")
+                    }
+                    Command::LoadState(slot) => {
+                        let loaded = std::fs::read(state_path(slot))
+                            .map_err(|e| e.to_string())
+                            .and_then(|state| vm.load_state(&state).map_err(|e| e.to_string()));
+                        match loaded {
+                            Ok(()) => {
+                                eprintln!("Loaded state {}", slot);
+                                running = true;
+                            }
+                            Err(e) => eprintln!("Unable to load state {}: {}", slot, e),
+                        }
+                    }
+                    Command::Rewind(enabled) => rewinding = enabled,
+                    Command::Pause(enabled) => paused = enabled,
+                    Command::Debug(line) => {
+                        if let Some(debugger) = &mut debugger {
+                            let held: Vec<Key> = keys.iter().copied().collect();
+                            if let Err(e) = debugger.command(&mut vm, &held, &line) {
+                                eprintln!("Emulation stopped: {}", e);
+                                running = false;
+                            }
+                        }
+                    }
+                    Command::Gdb(packet) => {
+                        if let Some(gdb) = &mut gdb {
+                            let held: Vec<Key> = keys.iter().copied().collect();
+                            if let Err(e) = gdb.handle(&mut vm, &held, packet) {
+                                eprintln!("Lost connection to GDB: {}", e);
+                            }
+                            // The stub reports errors itself and decides when to resume
+                            running = true;
+                        }
+                    }
+                    Command::Quit => {
+                        if let Some((path, movie)) = &recording {
+                            match std::fs::write(path, movie.to_bytes()) {
+                                Ok(()) => eprintln!("Recorded {} frames to {}", movie.len(), path),
+                                Err(e) => eprintln!("Unable to write movie: {}", e),
+                            }
+                        }
+                        if let Some(path) = &profile_path {
+                            write_profile(&vm, path);
+                        }
+                        return;
+                    }
+                }
+            }
+            if let Some(remaining) = FRAME_DURATION.checked_sub(last_instant.elapsed()) {
+                std::thread::sleep(remaining);
+            }
+            last_instant = Instant::now();
+
             keys.extend(rx_key_pressed.try_iter().flatten());
             for element in rx_key_released.try_iter().flatten() {
                 keys.remove(&element);
             }
             let keys: Vec<Key> = keys.clone().into_iter().collect();
-            vm.tick(&keys);
+            if rewinding {
+                if let Some(state) = rewind.pop() {
+                    vm.load_state(&state).expect("rewind state is invalid");
+                    running = true;
+                    frame -= 1;
+                    if let Some((_, movie)) = &mut recording {
+                        movie.truncate(frame);
+                    }
+                }
+            } else if running
+                && !paused
+                && !debugger.as_ref().is_some_and(Debugger::is_paused)
+                && !gdb.as_ref().is_some_and(GdbStub::is_paused)
+            {
+                // Movies drive the keys and speed until they run out
+                let (keys, speed) = playback
+                    .as_ref()
+                    .and_then(|movie| movie.frame(frame))
+                    .unwrap_or((keys, instructions_per_frame));
+                if playback.as_ref().is_some_and(|movie| movie.len() == frame) {
+                    eprintln!("Movie finished");
+                }
+                let result = if let Some(debugger) = &mut debugger {
+                    debugger.run_frame(&mut vm, &keys, speed)
+                } else if let Some(gdb) = &mut gdb {
+                    gdb.run_frame(&mut vm, &keys, speed)
+                } else {
+                    vm.run_frame(&keys, speed)
+                };
+                match result {
+                    Ok(()) => {
+                        if let Some((_, movie)) = &mut recording {
+                            movie.record(&keys, speed);
+                        }
+                        frame += 1;
+                        rewind.push(vm.save_state());
+                    }
+                    Err(e) => {
+                        eprintln!("Emulation stopped: {}", e);
+                        running = false;
+                    }
+                }
+            }
+            let (width, height) = vm.resolution();
             tx_buf
-                .send(vm.get_current_frame())
+                .send((vm.pixels().to_vec(), width, height))
                 .expect("unable to send buffer");
-
-            last_instant = Instant::now();
         }
     });
-    while window.is_open() && !window.is_key_down(minifb::Key::Escape) {
-        window
-            .get_keys_pressed(minifb::KeyRepeat::No)
-            .iter()
-            .for_each(|keys| {
-                tx_key_pressed
-                    .send(keys.iter().filter_map(|k| Key::from(*k)).collect())
-                    .expect("key send failed")
+    if matches.is_present("terminal") {
+        let style = if matches.is_present("braille") {
+            Style::Braille
+        } else {
+            Style::HalfBlock
+        };
+        let mut terminal = Terminal::new(style, palette)
+            .unwrap_or_else(|e| exit_with_error("Unable to set up the terminal", e));
+        let mut rewinding = false;
+        let mut paused = false;
+        let mut buffer = (vec![], 0, 0);
+        while terminal.update(FRAME_DURATION).is_ok() {
+            let pressed = terminal.keys_pressed().to_vec();
+            // Raw mode turns Ctrl+C into a key press
+            let quit = pressed.iter().any(|event| {
+                event.code == KeyCode::Esc
+                    || (event.code == KeyCode::Char('c')
+                        && event.modifiers.contains(KeyModifiers::CONTROL))
             });
-        window.get_keys_released().iter().for_each(|keys| {
+            if quit {
+                break;
+            }
+            if terminal.is_key_down(KeyCode::Backspace) != rewinding {
+                rewinding = !rewinding;
+                tx_command.send(Command::Rewind(rewinding)).ok();
+            }
+            let mut speed = instructions_per_frame;
+            for event in &pressed {
+                let repeat = event.kind == KeyEventKind::Repeat;
+                match event.code {
+                    KeyCode::Char('=') => speed = faster(speed),
+                    KeyCode::Char('-') => speed = slower(speed),
+                    KeyCode::Char('p') if !repeat => {
+                        paused = !paused;
+                        tx_command.send(Command::Pause(paused)).ok();
+                    }
+                    // Shift+F1-F8 saves to a slot, F1-F8 loads it
+                    KeyCode::F(slot @ 1..=8) if !repeat => {
+                        let command = if event.modifiers.contains(KeyModifiers::SHIFT) {
+                            Command::SaveState(slot as usize)
+                        } else {
+                            Command::LoadState(slot as usize)
+                        };
+                        tx_command.send(command).ok();
+                    }
+                    _ => {}
+                }
+            }
+            if speed != instructions_per_frame {
+                instructions_per_frame = speed;
+                tx_command
+                    .send(Command::SetSpeed(instructions_per_frame))
+                    .ok();
+            }
+
+            tx_key_pressed
+                .send(
+                    pressed
+                        .iter()
+                        .filter_map(|event| terminal_key(event.code))
+                        .collect(),
+                )
+                .ok();
             tx_key_released
-                .send(keys.iter().filter_map(|k| Key::from(*k)).collect())
-                .expect("key send failed")
+                .send(
+                    terminal
+                        .keys_released()
+                        .iter()
+                        .filter_map(|code| terminal_key(*code))
+                        .collect(),
+                )
+                .ok();
+
+            if let Some(next) = rx_buf.try_iter().last() {
+                buffer = next;
+            }
+            let status = title(instructions_per_frame, paused);
+            if terminal
+                .draw(&buffer.0, buffer.1, buffer.2, &status)
+                .is_err()
+            {
+                break;
+            }
+        }
+    } else {
+        let options = WindowOptions {
+            scale: Scale::X8,
+            resize: true,
+            ..WindowOptions::default()
+        };
+        let mut window = Window::new(
+            &title(instructions_per_frame, false),
+            HIRES_WIDTH,
+            HIRES_HEIGHT,
+            options,
+        )
+        .unwrap_or_else(|e| {
+            panic!("{}", e);
         });
 
-        match rx_buf.try_iter().last() {
-            Some(buffer) => window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap(),
-            None => window.update(),
+        // Limit to max ~60 fps update rate
+        window.limit_update_rate(Some(FRAME_DURATION));
+
+        let mut rewinding = false;
+        let mut paused = false;
+        while window.is_open() && !window.is_key_down(minifb::Key::Escape) {
+            // Holding backspace runs time backwards
+            if window.is_key_down(minifb::Key::Backspace) != rewinding {
+                rewinding = !rewinding;
+                tx_command.send(Command::Rewind(rewinding)).ok();
+            }
+            if window.is_key_pressed(minifb::Key::P, minifb::KeyRepeat::No) {
+                paused = !paused;
+                window.set_title(&title(instructions_per_frame, paused));
+                tx_command.send(Command::Pause(paused)).ok();
+            }
+            let speed = if window.is_key_pressed(minifb::Key::Equal, minifb::KeyRepeat::Yes) {
+                faster(instructions_per_frame)
+            } else if window.is_key_pressed(minifb::Key::Minus, minifb::KeyRepeat::Yes) {
+                slower(instructions_per_frame)
+            } else {
+                instructions_per_frame
+            };
+            if speed != instructions_per_frame {
+                instructions_per_frame = speed;
+                window.set_title(&title(instructions_per_frame, paused));
+                tx_command
+                    .send(Command::SetSpeed(instructions_per_frame))
+                    .ok();
+            }
+            // Shift+F1-F8 saves to a slot, F1-F8 loads it
+            let shift = window.is_key_down(minifb::Key::LeftShift)
+                || window.is_key_down(minifb::Key::RightShift);
+            for (slot, key) in STATE_SLOT_KEYS.iter().enumerate() {
+                if window.is_key_pressed(*key, minifb::KeyRepeat::No) {
+                    let command = if shift {
+                        Command::SaveState(slot + 1)
+                    } else {
+                        Command::LoadState(slot + 1)
+                    };
+                    tx_command.send(command).ok();
+                }
+            }
+
+            window
+                .get_keys_pressed(minifb::KeyRepeat::No)
+                .iter()
+                .for_each(|keys| {
+                    tx_key_pressed
+                        .send(keys.iter().filter_map(|k| key(*k)).collect())
+                        .ok();
+                });
+            window.get_keys_released().iter().for_each(|keys| {
+                tx_key_released
+                    .send(keys.iter().filter_map(|k| key(*k)).collect())
+                    .ok();
+            });
+
+            match rx_buf.try_iter().last() {
+                Some((pixels, width, height)) => {
+                    let buffer: Vec<u32> =
+                        pixels.iter().map(|pixel| palette.color(*pixel)).collect();
+                    window.update_with_buffer(&buffer, width, height).unwrap()
+                }
+                None => window.update(),
+            }
         }
     }
+
+    // Let the emulation thread write the recording before exiting
+    tx_command.send(Command::Quit).ok();
+    emulation.join().ok();
 }
@@ -0,0 +1,133 @@
+use chip_8::engine::key::Key;
+use chip_8::engine::mode::Mode;
+use chip_8::engine::quirks::Quirks;
+
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u8 = 1;
+
+// Everything needed to replay a session frame by frame: the settings the VM
+// was created with and, for every frame, the keys held and the speed.
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub mode: Mode,
+    pub quirks: Quirks,
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Frame {
+    keys: u16,
+    instructions: u16,
+}
+
+// FNV-1a, enough to tell ROMs apart
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], seed: u64, mode: Mode, quirks: Quirks) -> Self {
+        Self {
+            rom_hash: rom_hash(rom),
+            seed,
+            mode,
+            quirks,
+            frames: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn record(&mut self, keys: &[Key], instructions_per_frame: usize) {
+        self.frames.push(Frame {
+            keys: keys.iter().fold(0, |mask, key| mask | 1 << *key as u16),
+            instructions: instructions_per_frame as u16,
+        })
+    }
+
+    // Drops every frame from `frame` on, after rewinding while recording
+    pub fn truncate(&mut self, frame: usize) {
+        self.frames.truncate(frame)
+    }
+
+    // Keys held and instructions per frame, None past the end of the movie
+    pub fn frame(&self, frame: usize) -> Option<(Vec<Key>, usize)> {
+        self.frames.get(frame).map(|frame| {
+            let keys = Key::ALL
+                .iter()
+                .filter(|key| frame.keys & 1 << **key as u16 != 0)
+                .copied()
+                .collect();
+            (keys, frame.instructions as usize)
+        })
+    }
+
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), String> {
+        if rom_hash(rom) != self.rom_hash {
+            return Err(String::from("movie was recorded with a different ROM"));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.rom_hash.to_be_bytes());
+        bytes.extend_from_slice(&self.seed.to_be_bytes());
+        bytes.push(self.mode.id());
+        bytes.push(self.quirks.to_bits());
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        for frame in self.frames.iter() {
+            bytes.extend_from_slice(&frame.keys.to_be_bytes());
+            bytes.extend_from_slice(&frame.instructions.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut position = 0;
+        let mut take = |length: usize| {
+            let field = bytes
+                .get(position..position + length)
+                .ok_or_else(|| String::from("movie is truncated"));
+            position += length;
+            field
+        };
+        if take(4)? != MAGIC {
+            return Err(String::from("not a movie file"));
+        }
+        let version = take(1)?[0];
+        if version != VERSION {
+            return Err(format!("unsupported movie version {}", version));
+        }
+        let mut u64_field = [0; 8];
+        u64_field.copy_from_slice(take(8)?);
+        let rom_hash = u64::from_be_bytes(u64_field);
+        u64_field.copy_from_slice(take(8)?);
+        let seed = u64::from_be_bytes(u64_field);
+        let mode = Mode::from_id(take(1)?[0]).ok_or_else(|| String::from("unknown mode"))?;
+        let quirks = Quirks::from_bits(take(1)?[0]);
+        let mut count = [0; 4];
+        count.copy_from_slice(take(4)?);
+        let mut frames = vec![];
+        for _ in 0..u32::from_be_bytes(count) {
+            let frame = take(4)?;
+            frames.push(Frame {
+                keys: u16::from_be_bytes([frame[0], frame[1]]),
+                instructions: u16::from_be_bytes([frame[2], frame[3]]),
+            });
+        }
+        Ok(Self {
+            rom_hash,
+            seed,
+            mode,
+            quirks,
+            frames,
+        })
+    }
+}
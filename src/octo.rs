@@ -0,0 +1,690 @@
+use chip_8::engine::opcode::Opcode;
+use chip_8::engine::register::Register;
+use chip_8::engine::vm::PROGRAM_OFFSET;
+use std::collections::HashMap;
+
+// Catches macros that expand themselves forever
+const MAX_EXPANSIONS: usize = 10_000;
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+// A reference to a label that wasn't defined yet when it was used
+struct Fixup {
+    offset: usize,
+    name: String,
+    line: usize,
+    // `i := long`, a full 16 bit address after the F000 word
+    long: bool,
+}
+
+// Instructions that skip the next one when a condition is true, and when
+// it is false
+struct Condition {
+    when_true: Opcode,
+    when_false: Opcode,
+}
+
+struct Compiler {
+    tokens: Vec<Token>,
+    position: usize,
+    // The last token read, for errors
+    line: usize,
+    rom: Vec<u8>,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    // Start of every open `loop`, and the `while` jumps to its `again`
+    loops: Vec<(u16, Vec<usize>)>,
+    // The jump of every open `if ... begin`, to its `else` or `end`
+    blocks: Vec<usize>,
+    expansions: usize,
+}
+
+// Compiles Octo source into a ROM loaded at 0x200. Execution starts at the
+// `main` label, with a jump to it unless it comes first.
+pub fn compile(source: &str) -> Result<Vec<u8>, String> {
+    let mut compiler = Compiler {
+        tokens: tokenize(source),
+        position: 0,
+        line: 1,
+        rom: vec![],
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: vec![],
+        loops: vec![],
+        blocks: vec![],
+        expansions: 0,
+    };
+    compiler
+        .compile()
+        .map_err(|e| format!("line {}: {}", compiler.line, e))?;
+    Ok(compiler.rom)
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap();
+        for text in code.split_whitespace() {
+            tokens.push(Token {
+                text: text.to_string(),
+                line: index + 1,
+            });
+        }
+    }
+    tokens
+}
+
+fn register(token: &str) -> Option<u8> {
+    let digit = token.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+impl Compiler {
+    fn address(&self) -> usize {
+        PROGRAM_OFFSET as usize + self.rom.len()
+    }
+
+    fn compile(&mut self) -> Result<(), String> {
+        let starts_with_main =
+            self.tokens.len() >= 2 && self.tokens[0].text == ":" && self.tokens[1].text == "main";
+        if !starts_with_main {
+            self.reference("main", false);
+            self.emit(Opcode::JP(0));
+        }
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+        if !self.loops.is_empty() {
+            return Err(String::from("loop without again"));
+        }
+        if !self.blocks.is_empty() {
+            return Err(String::from("begin without end"));
+        }
+        if !self.labels.contains_key("main") {
+            return Err(String::from("the program has no main label"));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let address = *self
+                .labels
+                .get(&fixup.name)
+                .ok_or_else(|| format!("unknown label {}", fixup.name))?;
+            if fixup.long {
+                self.rom[fixup.offset..fixup.offset + 2].copy_from_slice(&address.to_be_bytes());
+            } else {
+                self.patch(fixup.offset, address)?;
+            }
+        }
+        if self.address() > 0x10000 {
+            return Err(String::from("program does not fit in memory"));
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| String::from("unexpected end of file"))?;
+        self.line = token.line;
+        self.position += 1;
+        Ok(token.text.clone())
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens
+            .get(self.position)
+            .map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(format!("expected {}, got {}", expected, token));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: Opcode) {
+        self.rom.extend(opcode.encode());
+    }
+
+    // Sets the address of the 2 byte instruction at `offset`
+    fn patch(&mut self, offset: usize, address: u16) -> Result<(), String> {
+        if address > 0xfff {
+            return Err(format!("0x{:x} is out of reach, use i := long", address));
+        }
+        self.rom[offset] = (self.rom[offset] & 0xf0) | (address >> 8) as u8;
+        self.rom[offset + 1] = address as u8;
+        Ok(())
+    }
+
+    // Records a use of a label, to resolve once every label is known
+    fn reference(&mut self, name: &str, long: bool) {
+        self.fixups.push(Fixup {
+            offset: self.rom.len() + if long { 2 } else { 0 },
+            name: name.to_string(),
+            line: self.line,
+            long,
+        });
+    }
+
+    fn register(&self, token: &str) -> Result<Register, String> {
+        let id = register(token)
+            .or_else(|| self.aliases.get(token).copied())
+            .ok_or_else(|| format!("expected a register, got {}", token))?;
+        Ok(Register::new(id as u16).unwrap())
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        register(token).is_some() || self.aliases.contains_key(token)
+    }
+
+    fn value(&self, token: &str) -> Option<i64> {
+        number(token).or_else(|| self.constants.get(token).copied())
+    }
+
+    // Values from `-(max + 1) / 2` up to `max`, negative ones wrap around
+    fn bounded(&self, token: &str, max: i64) -> Result<u16, String> {
+        let value = self
+            .value(token)
+            .ok_or_else(|| format!("expected a number, got {}", token))?;
+        if value > max || value < -(max + 1) / 2 {
+            return Err(format!("{} does not fit in 0x{:x}", token, max));
+        }
+        Ok((value & max) as u16)
+    }
+
+    fn byte(&self, token: &str) -> Result<u8, String> {
+        Ok(self.bounded(token, 0xff)? as u8)
+    }
+
+    fn nibble(&self, token: &str) -> Result<u8, String> {
+        Ok(self.bounded(token, 0xf)? as u8)
+    }
+
+    // The address of a label or a number, or a fixup for a label defined later
+    fn target(&mut self, token: &str, long: bool) -> Result<u16, String> {
+        if let Some(&address) = self.labels.get(token) {
+            if !long && address > 0xfff {
+                return Err(format!("0x{:x} is out of reach, use i := long", address));
+            }
+            return Ok(address);
+        }
+        if self.value(token).is_some() {
+            return self.bounded(token, if long { 0xffff } else { 0xfff });
+        }
+        self.name(token)?;
+        self.reference(token, long);
+        Ok(0)
+    }
+
+    // Names can't be mistaken for anything else
+    fn name(&self, token: &str) -> Result<(), String> {
+        if number(token).is_some() || register(token).is_some() || token.starts_with(':') {
+            return Err(format!("{} can't be used as a name", token));
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str) -> Result<(), String> {
+        self.name(name)?;
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(format!("{} is defined twice", name));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        if self.macros.contains_key(&token) {
+            return self.expand(&token);
+        }
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                self.labels.insert(name, self.address() as u16);
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let value = self.next()?;
+                let value = self
+                    .value(&value)
+                    .or_else(|| self.labels.get(&value).map(|address| *address as i64))
+                    .ok_or_else(|| format!("expected a number, got {}", value))?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.name(&name)?;
+                let target = self.next()?;
+                let register = self.register(&target)?;
+                self.aliases.insert(name, register.id);
+            }
+            ":macro" => self.define_macro()?,
+            ":call" => {
+                let target = self.next()?;
+                let address = self.target(&target, false)?;
+                self.emit(Opcode::CALL(address));
+            }
+            ":byte" => {
+                let value = self.next()?;
+                let byte = self.byte(&value)?;
+                self.rom.push(byte);
+            }
+            "loop" => self.loops.push((self.address() as u16, vec![])),
+            "while" => {
+                let condition = self.condition()?;
+                self.emit(condition.when_true);
+                let offset = self.rom.len();
+                self.emit(Opcode::JP(0));
+                self.loops
+                    .last_mut()
+                    .ok_or_else(|| String::from("while outside of a loop"))?
+                    .1
+                    .push(offset);
+            }
+            "again" => {
+                let (start, exits) = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| String::from("again without loop"))?;
+                let offset = self.rom.len();
+                self.emit(Opcode::JP(0));
+                self.patch(offset, start)?;
+                for offset in exits {
+                    self.patch(offset, self.address() as u16)?;
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()?.as_str() {
+                    // The next statement runs when the condition holds
+                    "then" => self.emit(condition.when_false),
+                    // Over the jump to `else` or `end` when it holds
+                    "begin" => {
+                        self.emit(condition.when_true);
+                        self.blocks.push(self.rom.len());
+                        self.emit(Opcode::JP(0));
+                    }
+                    other => return Err(format!("expected then or begin, got {}", other)),
+                }
+            }
+            "else" => {
+                let offset = self
+                    .blocks
+                    .pop()
+                    .ok_or_else(|| String::from("else without begin"))?;
+                self.blocks.push(self.rom.len());
+                self.emit(Opcode::JP(0));
+                self.patch(offset, self.address() as u16)?;
+            }
+            "end" => {
+                let offset = self
+                    .blocks
+                    .pop()
+                    .ok_or_else(|| String::from("end without begin"))?;
+                self.patch(offset, self.address() as u16)?;
+            }
+            "clear" => self.emit(Opcode::CLS),
+            ";" | "return" => self.emit(Opcode::RET),
+            "exit" => self.emit(Opcode::EXIT),
+            "lores" => self.emit(Opcode::LOW),
+            "hires" => self.emit(Opcode::HIGH),
+            "scroll-left" => self.emit(Opcode::SCL),
+            "scroll-right" => self.emit(Opcode::SCR),
+            "audio" => self.emit(Opcode::AUDIO),
+            "scroll-down" | "scroll-up" | "plane" => {
+                let value = self.next()?;
+                let n = self.nibble(&value)?;
+                self.emit(match token.as_str() {
+                    "scroll-down" => Opcode::SCD(n),
+                    "scroll-up" => Opcode::SCU(n),
+                    _ => Opcode::PLANE(n),
+                });
+            }
+            "jump" | "jump0" => {
+                let target = self.next()?;
+                let address = self.target(&target, false)?;
+                self.emit(if token == "jump" {
+                    Opcode::JP(address)
+                } else {
+                    Opcode::JPA(address)
+                });
+            }
+            "sprite" => {
+                let x = self.next()?;
+                let y = self.next()?;
+                let n = self.next()?;
+                let opcode = Opcode::DRW(self.register(&x)?, self.register(&y)?, self.nibble(&n)?);
+                self.emit(opcode);
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = self.next()?;
+                let x = self.register(&x)?;
+                self.emit(match token.as_str() {
+                    "bcd" => Opcode::LDB(x),
+                    "saveflags" => Opcode::LDRV(x),
+                    _ => Opcode::LDVR(x),
+                });
+            }
+            "save" | "load" => {
+                let x = self.next()?;
+                let x = self.register(&x)?;
+                let opcode = if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.next()?;
+                    let y = self.register(&y)?;
+                    if token == "save" {
+                        Opcode::SAVE(x, y)
+                    } else {
+                        Opcode::LOAD(x, y)
+                    }
+                } else if token == "save" {
+                    Opcode::LDIM(x)
+                } else {
+                    Opcode::LDMI(x)
+                };
+                self.emit(opcode);
+            }
+            "i" => self.index()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next()?;
+                let x = self.register(&x)?;
+                self.emit(match token.as_str() {
+                    "delay" => Opcode::LDDTV(x),
+                    "buzzer" => Opcode::LDST(x),
+                    _ => Opcode::PITCH(x),
+                });
+            }
+            _ if self.is_register(&token) => self.assignment(&token)?,
+            _ if self.value(&token).is_some() => {
+                let byte = self.byte(&token)?;
+                self.rom.push(byte);
+            }
+            // Anything else calls a subroutine
+            _ => {
+                let address = self.target(&token, false)?;
+                self.emit(Opcode::CALL(address));
+            }
+        }
+        Ok(())
+    }
+
+    // `:macro name arguments { body }`, braces in the body nest
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.next()?;
+        self.name(&name)?;
+        let mut arguments = vec![];
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            arguments.push(token);
+        }
+        let mut body = vec![];
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(self.tokens[self.position - 1].clone());
+        }
+        self.macros.insert(name, Macro { arguments, body });
+        Ok(())
+    }
+
+    // Replaces a macro and its arguments with the body
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("{} expands forever", name));
+        }
+        let mut values = HashMap::new();
+        for argument in self.macros[name].arguments.clone() {
+            values.insert(argument, self.next()?);
+        }
+        let line = self.line;
+        let body: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: values.get(&token.text).unwrap_or(&token.text).clone(),
+                line,
+            })
+            .collect();
+        self.tokens.splice(self.position..self.position, body);
+        Ok(())
+    }
+
+    // `vx == n`, `vx != vy`, `vx key` and `vx -key`
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.next()?;
+        let operator = self.next()?;
+        let condition = match operator.as_str() {
+            "key" => Condition {
+                when_true: Opcode::SKP(self.register(&x)?),
+                when_false: Opcode::SKNP(self.register(&x)?),
+            },
+            "-key" => Condition {
+                when_true: Opcode::SKNP(self.register(&x)?),
+                when_false: Opcode::SKP(self.register(&x)?),
+            },
+            "==" | "!=" => {
+                let y = self.next()?;
+                let (equal, not_equal) = if self.is_register(&y) {
+                    (
+                        Opcode::SE(self.register(&x)?, self.register(&y)?),
+                        Opcode::SNE(self.register(&x)?, self.register(&y)?),
+                    )
+                } else {
+                    let byte = self.byte(&y)?;
+                    (
+                        Opcode::SEI(self.register(&x)?, byte),
+                        Opcode::SNEI(self.register(&x)?, byte),
+                    )
+                };
+                if operator == "==" {
+                    Condition {
+                        when_true: equal,
+                        when_false: not_equal,
+                    }
+                } else {
+                    Condition {
+                        when_true: not_equal,
+                        when_false: equal,
+                    }
+                }
+            }
+            _ => return Err(format!("expected ==, !=, key or -key, got {}", operator)),
+        };
+        Ok(condition)
+    }
+
+    // `i := address`, `i := long address`, `i := hex vx`, `i += vx` ...
+    fn index(&mut self) -> Result<(), String> {
+        let operator = self.next()?;
+        let opcode = match operator.as_str() {
+            ":=" => match self.next()?.as_str() {
+                "hex" => {
+                    let x = self.next()?;
+                    Opcode::LDF(self.register(&x)?)
+                }
+                "bighex" => {
+                    let x = self.next()?;
+                    Opcode::LDHF(self.register(&x)?)
+                }
+                "long" => {
+                    let target = self.next()?;
+                    Opcode::LDIL(self.target(&target, true)?)
+                }
+                target => {
+                    let target = target.to_string();
+                    Opcode::LDII(self.target(&target, false)?)
+                }
+            },
+            "+=" => {
+                let x = self.next()?;
+                Opcode::ADDRI(self.register(&x)?)
+            }
+            _ => return Err(format!("expected := or +=, got {}", operator)),
+        };
+        self.emit(opcode);
+        Ok(())
+    }
+
+    // `vx := ...`, `vx += ...` and the other register operators
+    fn assignment(&mut self, target: &str) -> Result<(), String> {
+        let x = self.register(target)?;
+        let operator = self.next()?;
+        let operand = self.next()?;
+        let y = if self.is_register(&operand) {
+            Some(self.register(&operand)?)
+        } else {
+            None
+        };
+        let opcode = match (operator.as_str(), y) {
+            (":=", Some(y)) => Opcode::LD(x, y),
+            (":=", None) => match operand.as_str() {
+                "random" => {
+                    let mask = self.next()?;
+                    Opcode::RND(x, self.byte(&mask)?)
+                }
+                "key" => Opcode::LDK(x),
+                "delay" => Opcode::LDVDT(x),
+                _ => Opcode::LDI(x, self.byte(&operand)?),
+            },
+            ("+=", Some(y)) => Opcode::ADD(x, y),
+            ("+=", None) => Opcode::ADDI(x, self.byte(&operand)?),
+            ("-=", Some(y)) => Opcode::SUBR(x, y),
+            ("-=", None) => Opcode::ADDI(x, self.byte(&operand)?.wrapping_neg()),
+            ("=-", Some(y)) => Opcode::SUBN(x, y),
+            ("|=", Some(y)) => Opcode::OR(x, y),
+            ("&=", Some(y)) => Opcode::AND(x, y),
+            ("^=", Some(y)) => Opcode::XOR(x, y),
+            (">>=", Some(y)) => Opcode::SHR(x, y),
+            ("<<=", Some(y)) => Opcode::SHL(x, y),
+            _ => return Err(format!("can't use {} {} {}", target, operator, operand)),
+        };
+        self.emit(opcode);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::{disassemble, Syntax};
+    use chip_8::engine::mode::Mode;
+
+    // Code that starts at 0x1000, out of reach of 12 bit addresses
+    fn far(code: &str) -> String {
+        let padding = ":byte 0\n".repeat(0x1000 - PROGRAM_OFFSET as usize - 2);
+        format!(": main\njump main\n{}{}", padding, code)
+    }
+
+    #[test]
+    fn loops() {
+        let rom = compile(": main loop v0 += 1 while v0 != 5 again").unwrap();
+        assert_eq!(rom, [0x70, 0x01, 0x40, 0x05, 0x12, 0x08, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn if_else() {
+        let source = ": main if v0 == 3 begin v1 := 1 else v1 := 2 end v2 := 7";
+        let rom = compile(source).unwrap();
+        assert_eq!(
+            rom,
+            [0x30, 0x03, 0x12, 0x08, 0x61, 0x01, 0x12, 0x0a, 0x61, 0x02, 0x62, 0x07]
+        );
+    }
+
+    #[test]
+    fn forward_references_and_main() {
+        let rom = compile(": sprite 0xff : main i := sprite jump end : end").unwrap();
+        assert_eq!(rom, [0x12, 0x03, 0xff, 0xa2, 0x02, 0x12, 0x07]);
+    }
+
+    #[test]
+    fn out_of_reach() {
+        for code in [
+            ": far jump far",
+            ": far loop again",
+            ": far i := far",
+            "jump far : far",
+        ] {
+            let error = compile(&far(code)).unwrap_err();
+            assert!(
+                error.ends_with("is out of reach, use i := long"),
+                "{}: {}",
+                code,
+                error
+            );
+        }
+        assert!(compile(&far(": far i := long far")).is_ok());
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let roms = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("roms");
+        for entry in std::fs::read_dir(roms).unwrap() {
+            let path = entry.unwrap().path();
+            let rom = std::fs::read(&path).unwrap();
+            let source = disassemble(&rom, Mode::Chip8, Syntax::Octo);
+            assert_eq!(compile(&source), Ok(rom), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn unbalanced_blocks() {
+        assert_eq!(
+            compile(": main again"),
+            Err(String::from("line 1: again without loop"))
+        );
+        assert!(compile(": main loop").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+
+// Ring buffer of recent save states. Only the newest state is kept whole,
+// every older one is stored as a delta against the state that followed it:
+// the XOR of both with runs of zeros collapsed, so frames that only touch a
+// few bytes of memory cost a few bytes.
+pub struct Rewind {
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    // Oldest first, applying the last delta to `newest` gives the state before it
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(newest) = self.newest.take() {
+            self.deltas.push_back(encode(&state, &newest));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.newest = Some(state);
+    }
+
+    // Steps one state back, returning it
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let previous = decode(self.newest.as_ref()?, &delta);
+        self.newest = Some(previous.clone());
+        Some(previous)
+    }
+}
+
+fn put_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        output.push((length as u8 & 0x7f) | 0x80);
+        length >>= 7;
+    }
+    output.push(length as u8)
+}
+
+fn get_length(input: &[u8], position: &mut usize) -> usize {
+    let mut length = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input[*position];
+        *position += 1;
+        length |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return length;
+        }
+        shift += 7;
+    }
+}
+
+// Target length, then alternating runs of unchanged bytes and XORed literals
+fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| target[i] ^ base.get(i).copied().unwrap_or(0);
+    let mut delta = vec![];
+    put_length(&mut delta, target.len());
+    let mut i = 0;
+    while i < target.len() {
+        let start = i;
+        while i < target.len() && xor(i) == 0 {
+            i += 1;
+        }
+        put_length(&mut delta, i - start);
+        let start = i;
+        while i < target.len() && xor(i) != 0 {
+            i += 1;
+        }
+        put_length(&mut delta, i - start);
+        delta.extend((start..i).map(xor));
+    }
+    delta
+}
+
+fn decode(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let length = get_length(delta, &mut position);
+    let mut target: Vec<u8> = (0..length)
+        .map(|i| base.get(i).copied().unwrap_or(0))
+        .collect();
+    let mut i = 0;
+    while i < length {
+        i += get_length(delta, &mut position);
+        let literals = get_length(delta, &mut position);
+        for byte in target.iter_mut().skip(i).take(literals) {
+            *byte ^= delta[position];
+            position += 1;
+        }
+        i += literals;
+    }
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_round_trip() {
+        let base: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut target = base.clone();
+        target[3] ^= 0xff;
+        target[500..700].iter_mut().for_each(|byte| *byte = 0);
+        let cases = [
+            target.clone(),
+            base.clone(),
+            target[..10].to_vec(),
+            [&target[..], &[1, 2, 3]].concat(),
+            vec![],
+        ];
+        for target in cases {
+            let delta = encode(&base, &target);
+            assert_eq!(decode(&base, &delta), target);
+        }
+    }
+
+    #[test]
+    fn unchanged_runs_collapse() {
+        let base = vec![7; 4096];
+        let mut target = base.clone();
+        target[2000] = 8;
+        assert!(encode(&base, &target).len() < 10);
+    }
+
+    #[test]
+    fn pops_states_newest_first() {
+        let mut rewind = Rewind::new(2);
+        for state in 0..4u8 {
+            rewind.push(vec![state; 64]);
+        }
+        assert_eq!(rewind.pop(), Some(vec![2; 64]));
+        assert_eq!(rewind.pop(), Some(vec![1; 64]));
+        // Older states fell out of the ring
+        assert_eq!(rewind.pop(), None);
+    }
+}
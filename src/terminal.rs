@@ -0,0 +1,265 @@
+use chip_8::engine::palette::Palette;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Colors, Print, ResetColor, SetColors};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::collections::HashMap;
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+// Without release events a key counts as held until it stops repeating, the
+// first repeat comes after a longer delay than the rest
+const FIRST_REPEAT: Duration = Duration::from_millis(500);
+const REPEAT: Duration = Duration::from_millis(100);
+// Redrawn even when nothing changed, in case something else wrote to the terminal
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Style {
+    // Two pixels per character, each in its own colour
+    HalfBlock,
+    // Eight pixels per character, lit dots in the foreground colour whichever plane they're on
+    Braille,
+}
+
+pub struct Terminal {
+    stdout: Stdout,
+    style: Style,
+    palette: Palette,
+    // Only terminals with the kitty keyboard protocol report releases
+    releases: bool,
+    // When each key held down counts as released, if the terminal doesn't say
+    held: HashMap<KeyCode, Instant>,
+    pressed: Vec<KeyEvent>,
+    released: Vec<KeyCode>,
+    drawn: Option<(Vec<u8>, usize, usize, String)>,
+    drawn_at: Instant,
+}
+
+impl Terminal {
+    /// Switches to raw mode on the alternate screen until dropped
+    pub fn new(style: Style, palette: Palette) -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        if releases {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(Self {
+            stdout,
+            style,
+            palette,
+            releases,
+            held: HashMap::new(),
+            pressed: vec![],
+            released: vec![],
+            drawn: None,
+            drawn_at: Instant::now(),
+        })
+    }
+
+    /// Reads key events for up to `timeout`
+    pub fn update(&mut self, timeout: Duration) -> io::Result<()> {
+        self.pressed.clear();
+        self.released.clear();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if !event::poll(deadline.saturating_duration_since(now))? {
+                break;
+            }
+            if let Event::Key(mut key) = event::read()? {
+                // Shift changes the character pressed but not the one released
+                if let KeyCode::Char(c) = key.code {
+                    key.code = KeyCode::Char(c.to_ascii_lowercase());
+                }
+                match key.kind {
+                    KeyEventKind::Press | KeyEventKind::Repeat => {
+                        let timeout = if self.held.contains_key(&key.code) {
+                            key.kind = KeyEventKind::Repeat;
+                            REPEAT
+                        } else {
+                            FIRST_REPEAT
+                        };
+                        self.held.insert(key.code, now + timeout);
+                        self.pressed.push(key);
+                    }
+                    KeyEventKind::Release => {
+                        if self.held.remove(&key.code).is_some() {
+                            self.released.push(key.code);
+                        }
+                    }
+                }
+            }
+        }
+        if !self.releases {
+            let now = Instant::now();
+            let released = &mut self.released;
+            self.held.retain(|code, released_at| {
+                let held = now < *released_at;
+                if !held {
+                    released.push(*code);
+                }
+                held
+            });
+        }
+        Ok(())
+    }
+
+    pub fn is_key_down(&self, code: KeyCode) -> bool {
+        self.held.contains_key(&code)
+    }
+
+    /// Presses since the last update, including repeats
+    pub fn keys_pressed(&self) -> &[KeyEvent] {
+        &self.pressed
+    }
+
+    pub fn keys_released(&self) -> &[KeyCode] {
+        &self.released
+    }
+
+    /// Draws the pixel values in `buffer` with a status line underneath, skipping rows that
+    /// didn't change
+    pub fn draw(
+        &mut self,
+        buffer: &[u8],
+        width: usize,
+        height: usize,
+        status: &str,
+    ) -> io::Result<()> {
+        if width == 0 {
+            return Ok(());
+        }
+        let drawn = self.drawn.take();
+        let stale = self.drawn_at.elapsed() >= REDRAW_INTERVAL;
+        let previous = match &drawn {
+            Some((pixels, w, h, _)) if (*w, *h) == (width, height) && !stale => Some(&pixels[..]),
+            Some((_, w, h, _)) if (*w, *h) != (width, height) => {
+                queue!(self.stdout, Clear(ClearType::All))?;
+                None
+            }
+            _ => None,
+        };
+        if previous == Some(buffer) && drawn.as_ref().is_some_and(|drawn| drawn.3 == status) {
+            self.drawn = drawn;
+            return Ok(());
+        }
+        let rows = match self.style {
+            Style::HalfBlock => self.half_blocks(buffer, previous, width, height)?,
+            Style::Braille => self.braille(buffer, previous, width, height)?,
+        };
+        queue!(
+            self.stdout,
+            MoveTo(0, rows),
+            Clear(ClearType::CurrentLine),
+            Print(status)
+        )?;
+        self.stdout.flush()?;
+        self.drawn = Some((buffer.to_vec(), width, height, status.to_string()));
+        self.drawn_at = Instant::now();
+        Ok(())
+    }
+
+    // The upper half block in the top pixel's colour over the bottom one's
+    fn half_blocks(
+        &mut self,
+        buffer: &[u8],
+        previous: Option<&[u8]>,
+        width: usize,
+        height: usize,
+    ) -> io::Result<u16> {
+        for (row, pixels) in buffer.chunks(width * 2).take(height / 2).enumerate() {
+            let range = row * width * 2..(row + 1) * width * 2;
+            if previous.is_some_and(|previous| previous[range] == *pixels) {
+                continue;
+            }
+            queue!(self.stdout, MoveTo(0, row as u16))?;
+            let mut colors = None;
+            for x in 0..width {
+                let cell = (pixels[x], pixels[width + x]);
+                if colors != Some(cell) {
+                    colors = Some(cell);
+                    queue!(
+                        self.stdout,
+                        SetColors(Colors::new(
+                            color(self.palette.color(cell.0)),
+                            color(self.palette.color(cell.1))
+                        ))
+                    )?;
+                }
+                queue!(self.stdout, Print('▀'))?;
+            }
+            queue!(self.stdout, ResetColor)?;
+        }
+        Ok((height / 2) as u16)
+    }
+
+    // Braille dots are numbered down the left column then the right, with
+    // the bottom row added last
+    fn braille(
+        &mut self,
+        buffer: &[u8],
+        previous: Option<&[u8]>,
+        width: usize,
+        height: usize,
+    ) -> io::Result<u16> {
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        let rows = height.div_ceil(4);
+        for row in 0..rows {
+            let range = row * 4 * width..((row + 1) * 4 * width).min(buffer.len());
+            if previous.is_some_and(|previous| previous[range.clone()] == buffer[range]) {
+                continue;
+            }
+            let line: String = (0..width.div_ceil(2))
+                .map(|column| {
+                    let mut dots = 0;
+                    for (dy, bits) in DOTS.iter().enumerate() {
+                        for (dx, bit) in bits.iter().enumerate() {
+                            let (x, y) = (column * 2 + dx, row * 4 + dy);
+                            if x < width && y < height && buffer[y * width + x] != 0 {
+                                dots |= bit;
+                            }
+                        }
+                    }
+                    std::char::from_u32(0x2800 + dots).unwrap()
+                })
+                .collect();
+            let colors = Colors::new(color(self.palette.colors[1]), color(self.palette.colors[0]));
+            queue!(
+                self.stdout,
+                MoveTo(0, row as u16),
+                SetColors(colors),
+                Print(line),
+                ResetColor
+            )?;
+        }
+        Ok(rows as u16)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.releases {
+            execute!(self.stdout, PopKeyboardEnhancementFlags).ok();
+        }
+        execute!(self.stdout, ResetColor, Show, LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+fn color(pixel: u32) -> Color {
+    Color::Rgb {
+        r: (pixel >> 16) as u8,
+        g: (pixel >> 8) as u8,
+        b: pixel as u8,
+    }
+}